@@ -1,11 +1,7 @@
 use std::time::Duration;
 
 use indexmap::IndexSet;
-use novel_lang::{
-    exporter::export_rich_text,
-    parser,
-    types::{File, Value},
-};
+use novel_lang::{diagnostic::Diagnostic, exporter::export_rich_text, parser, types::File};
 
 fn main() {
     let file = read_file("./examples/inner_voices_pilot/pilot.nov").unwrap();
@@ -17,16 +13,17 @@ pub fn list_all_scenes(file: &File) {
     for (index, scene) in file.scenes.iter().enumerate() {
         let name = scene
             .name
-            .clone()
+            .as_deref()
+            .cloned()
             .unwrap_or_else(|| format!("Scene {index}"));
-        let summary = scene
+        let _summary = scene
             .meta
             .get("Summary")
-            .map(Value::as_string)
+            .map(|value| value.as_string())
             .unwrap_or_else(|| "...".to_string());
 
         println!("{index}. {name}");
-        //println!("-- {name} --\n{summary}\n");
+        //println!("-- {name} --\n{_summary}\n");
     }
 }
 
@@ -36,8 +33,8 @@ pub fn list_each_tag(file: &File, tag: &'static str) {
 
     let items = file.scenes.iter().flat_map(|s| s.items.iter());
     for item in items {
-        if let novel_lang::types::SceneItem::TaggedAction(_tag, rich_text) = item
-            && _tag == tag
+        if let novel_lang::types::SceneItem::TaggedAction(_tag, rich_text) = &item.node
+            && _tag.as_str() == tag
         {
             let entry = format!(
                 "- {}",
@@ -70,7 +67,7 @@ pub fn estimate_duration(file: &File) {
     for scene in file.scenes.iter() {
         scene_count += 1;
         for item in scene.items.iter() {
-            match item {
+            match &item.node {
                 ActionBlock(_) => {
                     duration_seconds += 2;
                     descriptions += 1;
@@ -109,12 +106,11 @@ fn format_duration(duration: Duration) -> String {
 
 pub fn read_file<P: AsRef<std::path::Path>>(path: P) -> Result<File, FileReadError> {
     let raw = std::fs::read_to_string(&path).map_err(FileReadError::IO)?;
-    let (_, file) = parser::file(&raw).map_err(|_| FileReadError::Parse)?;
-    Ok(file)
+    parser::parse(&raw).map_err(FileReadError::Parse)
 }
 
 #[derive(Debug)]
 pub enum FileReadError {
     IO(std::io::Error),
-    Parse,
+    Parse(Diagnostic),
 }
@@ -0,0 +1,227 @@
+use std::{borrow::Cow, fmt::Display, ops::Range};
+
+/// A problem found in a novel source file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    /// The byte range of the source the problem is about.
+    pub span: Range<usize>,
+    pub start: LineColumn,
+    pub end: LineColumn,
+    /// Descriptions of what could have been parsed at `span`.
+    pub expected: Vec<String>,
+    /// A description of what was actually found at `span`.
+    pub found: String,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic for a syntax error at `offset` in `source`.
+    pub fn new(source: &str, offset: usize, expected: Vec<String>) -> Self {
+        let rest = &source[offset..];
+        let (found, length) = match rest.chars().next() {
+            None => ("end of file".to_owned(), 0),
+            Some('\r' | '\n') => ("end of line".to_owned(), 0),
+            Some(_) => {
+                let token = rest.split(char::is_whitespace).next().unwrap_or(rest);
+                let token = match token.char_indices().nth(MAX_TOKEN_LENGTH) {
+                    Some((end, _)) => &token[..end],
+                    None => token,
+                };
+                (format!("`{token}`"), token.len())
+            }
+        };
+
+        let lines = LineIndex::new(source);
+        let span = offset..offset + length;
+
+        Diagnostic {
+            start: lines.line_column(span.start),
+            end: lines.line_column(span.end),
+            span,
+            expected,
+            found,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self.expected.as_slice() {
+            [] => format!("unexpected {}", self.found),
+            [expected] => format!("expected {expected}, found {}", self.found),
+            [expected @ .., last] => format!(
+                "expected {} or {last}, found {}",
+                expected.join(", "),
+                self.found
+            ),
+        }
+    }
+
+    /// Renders the diagnostic with a frame of the code around it, pointing at the problem.
+    pub fn render(&self, source: &str, path: &str) -> String {
+        render(Severity::Error, &self.message(), source, path, &self.span)
+    }
+}
+
+/// How bad a problem is.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    /// Not a problem, but worth knowing about.
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Renders a message about `span` of `source`, with a frame of the code around it pointing at it.
+pub fn render(
+    severity: Severity,
+    message: &str,
+    source: &str,
+    path: &str,
+    span: &Range<usize>,
+) -> String {
+    let lines = LineIndex::new(source);
+    let start = lines.line_column(span.start);
+    let end = lines.line_column(span.end);
+
+    let line_number = (start.line + 1).to_string();
+    let gutter = " ".repeat(line_number.len());
+    let line = source.lines().nth(start.line).unwrap_or("");
+    let indent = " ".repeat(start.column);
+    let underline = if start.line == end.line {
+        "^".repeat((end.column - start.column).max(1))
+    } else {
+        "^".to_owned()
+    };
+
+    format!(
+        "{severity}: {message}\n{gutter}--> {path}:{start}\n{gutter} |\n{line_number} | {line}\n{gutter} | {indent}{underline}\n"
+    )
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.start, self.message())
+    }
+}
+
+/// How many characters of the offending token to show in a diagnostic.
+const MAX_TOKEN_LENGTH: usize = 24;
+
+/// A zero-based line and column (in characters) in a source file.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for LineColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+/// Converts byte offsets in a source file into lines and columns.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Cow<'a, [usize]>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        LineIndex {
+            source,
+            line_starts: Cow::Owned(LineStarts::new(source).0),
+        }
+    }
+
+    pub fn line_column(&self, offset: usize) -> LineColumn {
+        let line = self.line(offset);
+        let column = self.source[self.line_starts[line]..offset].chars().count();
+
+        LineColumn { line, column }
+    }
+
+    /// Like [`LineIndex::line_column`], but counting columns in UTF-16 code units, like editors do.
+    pub fn line_column_utf16(&self, offset: usize) -> LineColumn {
+        let line = self.line(offset);
+        let column = self.source[self.line_starts[line]..offset]
+            .encode_utf16()
+            .count();
+
+        LineColumn { line, column }
+    }
+
+    /// The byte offset of a line and UTF-16 column, clamped to the end of the line.
+    pub fn offset_utf16(&self, position: LineColumn) -> usize {
+        let Some(&line_start) = self.line_starts.get(position.line) else {
+            return self.source.len();
+        };
+        let line_end = self
+            .line_starts
+            .get(position.line + 1)
+            .map_or(self.source.len(), |next| next - 1);
+        let line = self.source[line_start..line_end].trim_end_matches('\r');
+
+        let mut column = 0;
+        for (idx, c) in line.char_indices() {
+            if column >= position.column {
+                return line_start + idx;
+            }
+            column += c.len_utf16();
+        }
+
+        line_start + line.len()
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset) - 1
+    }
+}
+
+/// Where the lines of a source start, kept up to date as it's edited
+/// rather than found again in the whole source after every edit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineStarts(Vec<usize>);
+
+impl LineStarts {
+    pub fn new(source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+
+        LineStarts(line_starts)
+    }
+
+    /// Updates the line starts after the text in `range` was replaced with `inserted`.
+    pub fn edit(&mut self, range: Range<usize>, inserted: &str) {
+        let delta = inserted.len() as isize - range.len() as isize;
+        let removed_from = self.0.partition_point(|&start| start <= range.start);
+        let removed_to = self.0.partition_point(|&start| start <= range.end);
+        let added = inserted
+            .match_indices('\n')
+            .map(|(idx, _)| range.start + idx + 1);
+
+        let count = self.0.len();
+        self.0.splice(removed_from..removed_to, added);
+        let kept_from = removed_to + self.0.len() - count;
+        for start in &mut self.0[kept_from..] {
+            *start = start.wrapping_add_signed(delta);
+        }
+    }
+
+    /// An index of `source`, which these have to be the line starts of.
+    pub fn index<'a>(&'a self, source: &'a str) -> LineIndex<'a> {
+        LineIndex {
+            source,
+            line_starts: Cow::Borrowed(&self.0),
+        }
+    }
+}
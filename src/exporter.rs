@@ -41,11 +41,11 @@ pub fn export_html_outline(file: &File) -> String {
             main {
                 @if let Some(frontmatter) = &file.frontmatter {
                     div class="header" {
-                        @if let Some(title) = frontmatter.get("T") {
+                        @if let Some(title) = frontmatter.get("Title") {
                             div class="header-title" {(r_value(title))}
                         }
 
-                        @if let Some(subtitle) = frontmatter.get("S") {
+                        @if let Some(subtitle) = frontmatter.get("Subtitle") {
                             div class="header-subtitle" {(r_value(subtitle))}
                         }
                     }
@@ -68,7 +68,7 @@ fn r_scene(scene: &Scene, idx: usize) -> impl Renderable {
                 (
                     format!("{}. {}",
                     idx + 1,
-                    scene.name.clone().unwrap_or_else(|| format!("Scene {}", idx + 1)))
+                    scene.name.as_deref().cloned().unwrap_or_else(|| format!("Scene {}", idx + 1)))
                 )
             }
             div class="marker" id=(scene.name.as_ref().map(|name| name.replace(" ", "_"))) {}
@@ -88,7 +88,7 @@ fn r_scene_outline(scene: &Scene, idx: usize) -> impl Renderable {
                 (
                     format!("{}. {}",
                     idx + 1,
-                    scene.name.clone().unwrap_or_else(|| format!("Scene {}", idx + 1)))
+                    scene.name.as_deref().cloned().unwrap_or_else(|| format!("Scene {}", idx + 1)))
                 )
             }
             div class="marker" id=(scene.name.as_ref().map(|name| name.replace(" ", "_"))) {}
@@ -127,11 +127,12 @@ fn r_item(scene_item: &SceneItem) -> impl Renderable {
                 }
             SceneItem::SpoilerBlock(rich_text) => div class="scene-item-comment" {(r_rich_text(rich_text))},
             SceneItem::TaggedAction(tag, rich_text) => div class=(format!("scene-item-tagged-action tag-{}", tag.to_lowercase())) {
-                div class="scene-item-tagged-action-tag" {(tag)}
+                div class="scene-item-tagged-action-tag" {(tag.as_str())}
                 div class="scene-item-tagged-action-content" {(r_rich_text(rich_text))}
             },
             SceneItem::Cont(_) => div class="todo" {},
             SceneItem::If(_, _) => div class="todo" {},
+            SceneItem::Set(_, _) => div class="todo" {},
         }
     }
 }
@@ -142,9 +143,13 @@ fn r_value(value: &Value) -> impl Renderable {
             Value::RichText(rich_text) => (r_rich_text(rich_text)),
             Value::List(values) => div class="value_list" {
                 @for value in values {
-                    (r_value(value))
+                    (r_value(&value.node))
                 }
             },
+            Value::Reference(reference) => a class="reference" href=(reference.referent) {
+                (reference.alias.as_ref().unwrap_or(&reference.referent))
+            },
+            value => span class="value" { (value.as_string()) },
         }
     }
 }
@@ -166,6 +171,7 @@ fn r_rich_text_part(rich_text_part: &RichTextPart) -> impl Renderable {
             RichTextPart::Reference (
                 reference
             ) => a class="reference" href=(reference.referent) { (reference.alias.as_ref().unwrap_or(&reference.referent)) },
+            RichTextPart::Variable(name) => span class="variable" title=(name) { (format!("{{{name}}}")) },
             RichTextPart::FormattedSection(formatting_tag, rich_text) =>
                 @match formatting_tag {
                     crate::types::FormattingTag::Bold => {
@@ -183,7 +189,9 @@ fn r_rich_text_part(rich_text_part: &RichTextPart) -> impl Renderable {
                     crate::types::FormattingTag::Strikethrough => {
                         del { (r_rich_text(rich_text)) }
                     }
-                    crate::types::FormattingTag::Other(_) => div class="todo" {},
+                    crate::types::FormattingTag::Other(name) => {
+                        span class=(name) { (r_rich_text(rich_text)) }
+                    }
                 },
         }
     }
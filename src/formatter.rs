@@ -0,0 +1,377 @@
+use crate::{
+    migrate::Dialect,
+    parser::is_typed_value,
+    types::{
+        File, FormattingTag, Metadata, Reference, RichText, RichTextPart, Scene, SceneItem, Value,
+    },
+};
+
+/// The frontmatter keys written as the title block.
+const TITLE_KEYS: [&str; 2] = ["Title", "Subtitle"];
+
+/// How much the items in an `@IF` block are indented.
+const INDENT: &str = "    ";
+
+/// Writes a file back as novel source, in the canonical style of `dialect`.
+///
+/// The title and subtitle are written on lines of their own, after the rest of the frontmatter
+/// between `---` fences. Scene headers are written as `== Name ==`, items are separated by
+/// one blank line, metadata values are aligned, and whitespace at the ends of lines is trimmed.
+///
+/// Rewriting a script in another dialect is left to [`crate::migrate`],
+/// so this is usually given the dialect the file was written in, from [`Dialect::of`].
+pub fn format(file: &File, dialect: Dialect) -> String {
+    Writer {
+        normalize: true,
+        dialect,
+    }
+    .file(file)
+}
+
+/// Writes a file back as novel source, keeping all of the text in it as it is,
+/// so that parsing the source gives back the same file.
+///
+/// Like [`format`], it writes everything else in the canonical style, in the bare dialect.
+pub fn emit(file: &File) -> String {
+    Writer {
+        normalize: false,
+        dialect: Dialect::Bare,
+    }
+    .file(file)
+}
+
+struct Writer {
+    /// Whether to trim the whitespace around lines of text, and drop the lines left blank.
+    normalize: bool,
+    /// How dialogue lines and tagged actions are written.
+    dialect: Dialect,
+}
+
+impl Writer {
+    fn file(&self, file: &File) -> String {
+        let mut sections = vec![];
+
+        if let Some(frontmatter) = &file.frontmatter {
+            sections.extend(self.frontmatter(frontmatter));
+        }
+
+        sections.extend(file.scenes.iter().map(|scene| self.scene(scene)));
+
+        let mut source = sections.join("\n\n");
+        source.push('\n');
+        source
+    }
+
+    /// The frontmatter between `---` fences, then the title block, if the title can be written as one.
+    fn frontmatter(&self, frontmatter: &Metadata) -> Vec<String> {
+        let title_block = self.title_block(frontmatter);
+        let fenced: Metadata = frontmatter
+            .iter()
+            .filter(|(key, _)| title_block.is_none() || !TITLE_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        let mut sections = vec![];
+        if !fenced.is_empty() {
+            sections.push(format!("---\n{}\n---", self.meta(&fenced)));
+        }
+        sections.extend(title_block);
+        sections
+    }
+
+    fn title_block(&self, frontmatter: &Metadata) -> Option<String> {
+        let title = self.title_line(frontmatter.get("Title")?)?;
+
+        match frontmatter.get("Subtitle") {
+            Some(subtitle) => Some(format!("{title}\n{}", self.title_line(subtitle)?)),
+            None => Some(title),
+        }
+    }
+
+    /// A value as a line of the title block, unless it would be read back as something else.
+    fn title_line(&self, value: &Value) -> Option<String> {
+        let Value::RichText(rich_text) = value else {
+            return None;
+        };
+        let line = self.text(rich_text, Position::LineStart);
+
+        let key = line.trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+        let is_entry =
+            key.len() < line.len() && key.trim_start_matches([' ', '\t']).starts_with(':');
+        let is_plain = !line.is_empty()
+            && !line.starts_with(char::is_whitespace)
+            && !line.starts_with("---")
+            && !line.contains('\n');
+
+        (is_plain && !is_entry).then_some(line)
+    }
+
+    fn scene(&self, scene: &Scene) -> String {
+        let mut header = match &scene.name {
+            Some(name) => format!("== {} ==", name.node),
+            None => "====".to_owned(),
+        };
+
+        if !scene.meta.is_empty() {
+            header.push('\n');
+            header.push_str(&self.meta(&scene.meta));
+        }
+
+        let items = scene.items.iter().map(|item| self.item(item, ""));
+        std::iter::once(header)
+            .chain(items)
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// One `Key: value` line for each entry, with the values aligned.
+    fn meta(&self, meta: &Metadata) -> String {
+        let width = meta.keys().map(|key| key.len()).max().unwrap_or(0);
+
+        meta.iter()
+            .map(|(key, value)| {
+                let padding = " ".repeat(width - key.len());
+                format!("{key}:{padding} {}", self.value(value))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn value(&self, value: &Value) -> String {
+        match value {
+            Value::RichText(rich_text) => {
+                let text = self.text(rich_text, Position::Inline);
+                match is_typed_value(&text) {
+                    true => self.text(rich_text, Position::Value),
+                    false => text,
+                }
+            }
+            Value::List(values) => values
+                .iter()
+                .map(|value| self.value(value))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Value::Reference(target) => format!("[{}]", reference(target)),
+            // Typed values are written as they were, so `1.50` isn't turned into `1.5`.
+            _ => value.as_string(),
+        }
+    }
+
+    /// Writes an item with its first line at `indent`, along with all of its other lines.
+    fn item(&self, item: &SceneItem, indent: &str) -> String {
+        let lines = match item {
+            SceneItem::ActionBlock(rich_text) => self
+                .text(rich_text, Position::LineStart)
+                .split('\n')
+                .map(str::to_owned)
+                .collect(),
+            SceneItem::DialogueBlock { speaker, block } => {
+                let lines = block
+                    .iter()
+                    .map(|rich_text| self.text(rich_text, Position::LineStart));
+
+                match self.dialect {
+                    Dialect::Bare => std::iter::once(format!("[{}]", reference(speaker)))
+                        .chain(lines)
+                        .collect(),
+                    Dialect::Dashed => {
+                        // Dashed lines without a speaker written before them continue the last one.
+                        let speaker =
+                            (!speaker.span.is_empty()).then(|| format!("[{}]", reference(speaker)));
+                        let lines = lines
+                            .filter(|line| !self.normalize || !line.trim().is_empty())
+                            .map(|line| format!("- {line}"));
+                        speaker.into_iter().chain(lines).collect()
+                    }
+                }
+            }
+            SceneItem::SpoilerBlock(rich_text) => {
+                vec![format!("// {}", self.text(rich_text, Position::Inline))]
+            }
+            SceneItem::TaggedAction(tag, rich_text) => {
+                let text = self.text(rich_text, Position::Inline);
+                match self.dialect {
+                    Dialect::Bare => vec![format!("#{} {text}", tag.node)],
+                    Dialect::Dashed => vec![format!("> {}: {text}", tag.node)],
+                }
+            }
+            SceneItem::Cont(target) => vec![format!("@CONT {}", reference(target))],
+            SceneItem::If(condition, items) => {
+                let condition = self.text(condition, Position::Inline);
+
+                match items.as_slice() {
+                    [only] if is_directive(only) => {
+                        vec![format!("@IF {condition} {}", self.item(only, ""))]
+                    }
+                    items => {
+                        let inner = format!("{indent}{INDENT}");
+                        let items = items
+                            .iter()
+                            .map(|item| self.item(item, &inner))
+                            .collect::<Vec<_>>()
+                            .join("\n\n");
+                        let body = match items.is_empty() {
+                            true => String::new(),
+                            false => format!("{items}\n"),
+                        };
+
+                        return format!("{indent}@IF {condition} {{\n{body}{indent}}}");
+                    }
+                }
+            }
+            SceneItem::Set(name, None) => vec![format!("@SET {}", name.node)],
+            SceneItem::Set(name, Some(value)) => {
+                vec![format!("@SET {} = {}", name.node, self.value(value))]
+            }
+        };
+
+        self.indent(lines, indent)
+    }
+
+    /// Joins the lines of an item, with the first one at `indent`.
+    ///
+    /// When normalizing, the other lines are re-indented too. Otherwise, they already have
+    /// whatever indentation they were written with as part of their text.
+    fn indent(&self, lines: Vec<String>, indent: &str) -> String {
+        if !self.normalize {
+            return format!("{indent}{}", lines.join("\n"));
+        }
+
+        lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("{indent}{line}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn text(&self, rich_text: &RichText, position: Position) -> String {
+        let text = write_rich_text(rich_text, position, "");
+        match self.normalize {
+            true => text.trim().to_owned(),
+            false => text,
+        }
+    }
+}
+
+/// Whether an item fits on the same line as the `@IF` guarding it.
+fn is_directive(item: &SceneItem) -> bool {
+    match item {
+        SceneItem::Cont(_) | SceneItem::Set(..) => true,
+        SceneItem::If(_, items) => matches!(items.as_slice(), [only] if is_directive(only)),
+        _ => false,
+    }
+}
+
+fn reference(reference: &Reference) -> String {
+    match &reference.alias {
+        Some(alias) => format!("{}|{alias}", reference.referent),
+        None => reference.referent.clone(),
+    }
+}
+
+/// Where some rich text is written, which decides what in it needs escaping.
+#[derive(Clone, Copy, PartialEq)]
+enum Position {
+    /// At the start of a line, where `@` would start a directive, `}` would close a block,
+    /// `#` or `>` would start a tagged action and `- ` would start a line of dialogue.
+    LineStart,
+    /// After something else on the same line.
+    Inline,
+    /// In a value that would otherwise be read as a typed value, where `,` would separate
+    /// the items of a list and `-` would start a number or a date.
+    Value,
+}
+
+/// Writes rich text as source, escaping whatever would otherwise be parsed as something else.
+///
+/// `delimiters` are the formatting delimiters of the sections the text is in.
+fn write_rich_text(rich_text: &RichText, position: Position, delimiters: &str) -> String {
+    let mut source = String::new();
+    let count = rich_text.0.len();
+
+    for (idx, part) in rich_text.0.iter().enumerate() {
+        let position = match idx {
+            0 => position,
+            _ if position == Position::Value => position,
+            _ => Position::Inline,
+        };
+
+        match &part.node {
+            RichTextPart::Text(text) => {
+                let is_last = idx + 1 == count;
+                escape(text, position, delimiters, is_last, &mut source);
+            }
+            RichTextPart::Reference(reference) => {
+                source.push_str(&format!("[[{}]]", self::reference(reference)));
+            }
+            RichTextPart::Variable(name) => source.push_str(&format!("{{{name}}}")),
+            RichTextPart::FormattedSection(FormattingTag::Other(name), content) => {
+                let content = write_rich_text(content, Position::Inline, delimiters);
+                source.push_str(&format!("[{name}]{content}[/{name}]"));
+            }
+            RichTextPart::FormattedSection(tag, content) => {
+                let delimiter = delimiter(tag);
+                let delimiters = format!("{delimiters}{delimiter}");
+                let content = write_rich_text(content, Position::Inline, &delimiters);
+                source.push_str(&format!("{delimiter}{content}{delimiter}"));
+            }
+        }
+    }
+
+    source
+}
+
+fn delimiter(tag: &FormattingTag) -> &str {
+    match tag {
+        FormattingTag::Bold => "**",
+        FormattingTag::Italic => "*",
+        FormattingTag::BoldItalic => "***",
+        FormattingTag::Underline => "__",
+        FormattingTag::Strikethrough => "~~",
+        FormattingTag::Other(_) => unreachable!("tags are written with their name"),
+    }
+}
+
+fn escape(text: &str, position: Position, delimiters: &str, is_last: bool, source: &mut String) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut position = position;
+
+    for (idx, &c) in chars.iter().enumerate() {
+        let previous = idx.checked_sub(1).map(|idx| chars[idx]);
+        let next = chars.get(idx + 1).copied();
+
+        // Markers with whitespace around them, like in `2 * 3`, can't open or close sections.
+        let is_loose =
+            previous.is_some_and(char::is_whitespace) && next.map_or(is_last, char::is_whitespace);
+        let is_intraword =
+            previous.is_some_and(char::is_alphanumeric) && next.is_some_and(char::is_alphanumeric);
+
+        let needs_escape = match c {
+            '\\' | '[' | ']' | '=' | '{' => true,
+            '*' | '~' => !is_loose || delimiters.contains(c),
+            '_' => !is_intraword && (!is_loose || delimiters.contains(c)),
+            '@' | '}' | '#' | '>' => position != Position::Inline,
+            '-' => match position {
+                Position::LineStart => next.is_some_and(|next| next == ' ' || next == '\t'),
+                Position::Inline => false,
+                Position::Value => true,
+            },
+            ',' => position == Position::Value,
+            _ => false,
+        };
+
+        if needs_escape {
+            source.push('\\');
+        }
+        source.push(c);
+
+        position = match c {
+            '\n' => Position::LineStart,
+            _ if position == Position::Value => position,
+            c if c.is_whitespace() => position,
+            _ => Position::Inline,
+        };
+    }
+}
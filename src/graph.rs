@@ -0,0 +1,356 @@
+//! The flow of a script from scene to scene.
+//!
+//! A scene goes on to the one written below it, unless it continues somewhere else
+//! with an `@CONT`. An `@CONT` inside an `@IF` only continues there under its condition,
+//! so the scene may still go on to the next one.
+
+use crate::{
+    diagnostic::Severity,
+    types::{File, Reference, RichText, Scene, SceneItem, Span, Spanned},
+};
+use std::collections::{HashMap, VecDeque};
+
+mod export;
+
+/// The scenes of a file and the ways to go from one to another.
+///
+/// Scenes are referred to by their position in [`File::scenes`].
+pub struct Graph<'a> {
+    pub scenes: &'a [Spanned<Scene>],
+    pub edges: Vec<Edge<'a>>,
+    /// The `@CONT`s to scenes that don't exist, along with the scene they're in
+    /// and the conditions of the `@IF`s they're inside, from the outermost in.
+    pub dangling: Vec<(usize, &'a Spanned<Reference>, Vec<&'a RichText>)>,
+    /// The positions in `edges` of the ways out of each scene.
+    by_scene: Vec<Vec<usize>>,
+}
+
+/// A way to go from one scene to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge<'a> {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdgeKind<'a> {
+    /// The scene ends and the next one starts.
+    FallThrough,
+    /// An `@CONT` to the scene.
+    Cont(&'a Spanned<Reference>),
+    /// An `@CONT` to the scene inside `@IF`s, with their conditions from the outermost in.
+    If(Vec<&'a RichText>, &'a Spanned<Reference>),
+}
+
+/// Something worth pointing out about the flow of a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub span: Span,
+    pub message: String,
+}
+
+impl<'a> Graph<'a> {
+    pub fn new(file: &'a File) -> Self {
+        let scenes = file.scenes.as_slice();
+
+        let mut by_name = HashMap::new();
+        for (idx, scene) in scenes.iter().enumerate() {
+            if let Some(name) = &scene.name {
+                by_name.entry(name.node.as_str()).or_insert(idx);
+            }
+        }
+
+        let mut graph = Graph {
+            scenes,
+            edges: vec![],
+            dangling: vec![],
+            by_scene: vec![vec![]; scenes.len()],
+        };
+
+        for (from, scene) in scenes.iter().enumerate() {
+            let continues = graph.add_conts(from, &scene.items, &mut vec![], &by_name);
+
+            if !continues && from + 1 < scenes.len() {
+                graph.edges.push(Edge {
+                    from,
+                    to: from + 1,
+                    kind: EdgeKind::FallThrough,
+                });
+            }
+        }
+
+        for (idx, edge) in graph.edges.iter().enumerate() {
+            graph.by_scene[edge.from].push(idx);
+        }
+
+        graph
+    }
+
+    /// Adds an edge for every `@CONT` in `items`,
+    /// returning whether any of them continues no matter the conditions.
+    fn add_conts(
+        &mut self,
+        from: usize,
+        items: &'a [Spanned<SceneItem>],
+        conditions: &mut Vec<&'a RichText>,
+        by_name: &HashMap<&str, usize>,
+    ) -> bool {
+        let mut continues = false;
+
+        for item in items {
+            match &item.node {
+                SceneItem::Cont(target) => {
+                    continues |= conditions.is_empty();
+
+                    let Some(&to) = by_name.get(target.referent.as_str()) else {
+                        self.dangling.push((from, target, conditions.clone()));
+                        continue;
+                    };
+                    let kind = match conditions.is_empty() {
+                        true => EdgeKind::Cont(target),
+                        false => EdgeKind::If(conditions.clone(), target),
+                    };
+                    self.edges.push(Edge { from, to, kind });
+                }
+                SceneItem::If(condition, items) => {
+                    conditions.push(condition);
+                    self.add_conts(from, items, conditions, by_name);
+                    conditions.pop();
+                }
+                _ => {}
+            }
+        }
+
+        continues
+    }
+
+    /// The ways to go on from a scene, in the order they're written.
+    pub fn outgoing(&self, scene: usize) -> impl Iterator<Item = &Edge<'a>> {
+        self.by_scene[scene].iter().map(|&idx| &self.edges[idx])
+    }
+
+    /// The scenes that can't be reached from the first one.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let mut reached = vec![false; self.scenes.len()];
+        let mut queue = VecDeque::new();
+        if !self.scenes.is_empty() {
+            reached[0] = true;
+            queue.push_back(0);
+        }
+
+        while let Some(scene) = queue.pop_front() {
+            for edge in self.outgoing(scene) {
+                if !reached[edge.to] {
+                    reached[edge.to] = true;
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+
+        (0..self.scenes.len())
+            .filter(|&idx| !reached[idx])
+            .collect()
+    }
+
+    /// The scenes the story ends at, which don't go on anywhere.
+    ///
+    /// Scenes that only go on to scenes that don't exist are left out, as they were meant to go on.
+    pub fn dead_ends(&self) -> Vec<usize> {
+        let mut meant_to_go_on = vec![false; self.scenes.len()];
+        for (from, ..) in self.dangling.iter() {
+            meant_to_go_on[*from] = true;
+        }
+
+        (0..self.scenes.len())
+            .filter(|&idx| self.by_scene[idx].is_empty() && !meant_to_go_on[idx])
+            .collect()
+    }
+
+    /// The groups of scenes that can lead back to each other, each in order.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let mut cycles: Vec<Vec<usize>> = Tarjan::new(self)
+            .components()
+            .into_iter()
+            .filter(|component| match component.as_slice() {
+                [scene] => self.outgoing(*scene).any(|edge| edge.to == *scene),
+                _ => true,
+            })
+            .map(|mut component| {
+                component.sort();
+                component
+            })
+            .collect();
+        cycles.sort();
+        cycles
+    }
+
+    /// Everything worth pointing out about the flow, in the order it's written.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+
+        for (_, target, _) in self.dangling.iter() {
+            problems.push(Problem {
+                severity: Severity::Error,
+                span: target.span.clone(),
+                message: format!("there is no scene called `{}`", target.referent),
+            });
+        }
+
+        for idx in self.unreachable() {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                span: self.header_span(idx),
+                message: format!("`{}` can't be reached from the start", self.name(idx)),
+            });
+        }
+
+        for cycle in self.cycles() {
+            let message = match cycle.as_slice() {
+                [scene] => format!("`{}` can lead back to itself", self.name(*scene)),
+                [scenes @ .., last] => format!(
+                    "{} and `{}` can lead back to each other",
+                    scenes
+                        .iter()
+                        .map(|scene| format!("`{}`", self.name(*scene)))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    self.name(*last)
+                ),
+                [] => unreachable!(),
+            };
+            problems.push(Problem {
+                severity: Severity::Note,
+                span: self.header_span(cycle[0]),
+                message,
+            });
+        }
+
+        for idx in self.dead_ends() {
+            problems.push(Problem {
+                severity: Severity::Note,
+                span: self.header_span(idx),
+                message: format!("the story ends at `{}`", self.name(idx)),
+            });
+        }
+
+        problems.sort_by_key(|problem| (problem.span.start, problem.severity));
+        problems
+    }
+
+    /// The name of a scene, or its position if it has none.
+    pub fn name(&self, scene: usize) -> String {
+        self.scenes[scene].name_or_position(scene)
+    }
+
+    /// The span of a scene's name, or the start of its header if it has none.
+    ///
+    /// The items before the first scene header have no header, so it spans nothing where they start.
+    fn header_span(&self, scene: usize) -> Span {
+        let scene = &self.scenes[scene];
+        let start = scene.span.start;
+        match &scene.name {
+            Some(name) => name.span.clone(),
+            None if scene
+                .items
+                .first()
+                .is_some_and(|item| item.span.start == start) =>
+            {
+                start..start
+            }
+            None => start..start + "==".len(),
+        }
+    }
+}
+
+/// Finds the strongly connected components of a graph, with Tarjan's algorithm.
+struct Tarjan<'g, 'a> {
+    graph: &'g Graph<'a>,
+    index: Vec<Option<usize>>,
+    low_link: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'g, 'a> Tarjan<'g, 'a> {
+    fn new(graph: &'g Graph<'a>) -> Self {
+        let len = graph.scenes.len();
+        Tarjan {
+            graph,
+            index: vec![None; len],
+            low_link: vec![0; len],
+            on_stack: vec![false; len],
+            stack: vec![],
+            next_index: 0,
+            components: vec![],
+        }
+    }
+
+    fn components(mut self) -> Vec<Vec<usize>> {
+        for scene in 0..self.graph.scenes.len() {
+            if self.index[scene].is_none() {
+                self.visit(scene);
+            }
+        }
+        self.components
+    }
+
+    /// Visits every scene reachable from `root` depth first.
+    ///
+    /// Scripts can be long enough to overflow the stack if this recursed,
+    /// so the scenes being visited are kept along with how many of their edges were followed.
+    fn visit(&mut self, root: usize) {
+        let graph = self.graph;
+        let mut visiting = vec![(root, 0)];
+        self.enter(root);
+
+        while let Some((scene, followed)) = visiting.last_mut() {
+            let scene = *scene;
+
+            if let Some(&edge) = graph.by_scene[scene].get(*followed) {
+                *followed += 1;
+
+                let to = graph.edges[edge].to;
+                match self.index[to] {
+                    None => {
+                        self.enter(to);
+                        visiting.push((to, 0));
+                    }
+                    Some(index) if self.on_stack[to] => {
+                        self.low_link[scene] = self.low_link[scene].min(index);
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            visiting.pop();
+            if let Some(&(parent, _)) = visiting.last() {
+                self.low_link[parent] = self.low_link[parent].min(self.low_link[scene]);
+            }
+
+            if Some(self.low_link[scene]) == self.index[scene] {
+                let mut component = vec![];
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack[member] = false;
+                    component.push(member);
+                    if member == scene {
+                        break;
+                    }
+                }
+                self.components.push(component);
+            }
+        }
+    }
+
+    fn enter(&mut self, scene: usize) {
+        self.index[scene] = Some(self.next_index);
+        self.low_link[scene] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(scene);
+        self.on_stack[scene] = true;
+    }
+}
@@ -0,0 +1,223 @@
+//! Writing a scene graph out for tools that draw graphs, like Graphviz and Mermaid.
+
+use super::{EdgeKind, Graph};
+use crate::types::RichText;
+
+/// Fill colors for scenes, given out to each place in the order they first appear in.
+const PALETTE: &[&str] = &[
+    "#fde2e4", "#e2ece9", "#fff1e6", "#dfe7fd", "#f0efeb", "#e8dff5", "#fcf4dd", "#ddedea",
+];
+
+/// How many characters a line of a summary may have before it wraps.
+const SUMMARY_WIDTH: usize = 40;
+
+/// What a scene looks like in a drawing of the graph.
+struct Node {
+    label: Vec<String>,
+    color: Option<&'static str>,
+}
+
+/// What a way to go from one scene to another looks like in a drawing of the graph.
+struct Line {
+    from: String,
+    to: String,
+    label: Option<String>,
+    /// Whether the scene just ends and the next one starts, which is drawn dashed.
+    falls_through: bool,
+}
+
+impl<'a> Graph<'a> {
+    /// The graph in the DOT language of Graphviz.
+    pub fn to_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+
+        let mut dot = String::from("digraph {\n");
+        dot.push_str("    node [shape=box, style=\"rounded,filled\", fillcolor=white];\n");
+
+        for (id, node) in self.nodes() {
+            let label = quote(&node.label.join("\n")).replace('\n', "\\n");
+            match node.color {
+                Some(color) => dot.push_str(&format!(
+                    "    {id} [label={label}, fillcolor=\"{color}\"];\n"
+                )),
+                None => dot.push_str(&format!("    {id} [label={label}];\n")),
+            }
+        }
+
+        for (id, name) in self.missing() {
+            dot.push_str(&format!(
+                "    {id} [label={}, style=dashed, color=red];\n",
+                quote(&name)
+            ));
+        }
+
+        for line in self.lines() {
+            let mut attributes = vec![];
+            if let Some(label) = &line.label {
+                attributes.push(format!("label={}", quote(label)));
+            }
+            if line.falls_through {
+                attributes.push("style=dashed".to_owned());
+            }
+
+            match attributes.is_empty() {
+                true => dot.push_str(&format!("    {} -> {};\n", line.from, line.to)),
+                false => dot.push_str(&format!(
+                    "    {} -> {} [{}];\n",
+                    line.from,
+                    line.to,
+                    attributes.join(", ")
+                )),
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The graph as a Mermaid flowchart.
+    pub fn to_mermaid(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('"', "#quot;"));
+
+        let mut mermaid = String::from("flowchart TD\n");
+
+        for (id, node) in self.nodes() {
+            let label = quote(&node.label.join("\n")).replace('\n', "<br/>");
+            mermaid.push_str(&format!("    {id}[{label}]\n"));
+        }
+
+        for (id, name) in self.missing() {
+            mermaid.push_str(&format!("    {id}[{}]\n", quote(&name)));
+        }
+
+        for line in self.lines() {
+            let arrow = match line.falls_through {
+                true => "-.->",
+                false => "-->",
+            };
+            match &line.label {
+                Some(label) => mermaid.push_str(&format!(
+                    "    {} {arrow}|{}| {}\n",
+                    line.from,
+                    quote(label),
+                    line.to
+                )),
+                None => mermaid.push_str(&format!("    {} {arrow} {}\n", line.from, line.to)),
+            }
+        }
+
+        for (id, node) in self.nodes() {
+            if let Some(color) = node.color {
+                mermaid.push_str(&format!("    style {id} fill:{color}\n"));
+            }
+        }
+        for (id, _) in self.missing() {
+            mermaid.push_str(&format!("    style {id} stroke:red,stroke-dasharray:5 5\n"));
+        }
+
+        mermaid
+    }
+
+    /// Every scene, labeled with its name and summary, and colored by where it happens.
+    fn nodes(&self) -> Vec<(String, Node)> {
+        let mut places: Vec<String> = vec![];
+
+        self.scenes
+            .iter()
+            .enumerate()
+            .map(|(idx, scene)| {
+                let mut label = vec![self.name(idx)];
+                if let Some(summary) = scene.meta.get("Summary") {
+                    label.extend(wrap(&summary.as_string(), SUMMARY_WIDTH));
+                }
+
+                let color = scene.meta.get("Where").map(|place| {
+                    let place = place.as_string();
+                    let idx = places.iter().position(|known| *known == place);
+                    let idx = idx.unwrap_or_else(|| {
+                        places.push(place);
+                        places.len() - 1
+                    });
+                    PALETTE[idx % PALETTE.len()]
+                });
+
+                (format!("scene{idx}"), Node { label, color })
+            })
+            .collect()
+    }
+
+    /// The scenes `@CONT`s go to but don't exist, by name.
+    fn missing(&self) -> Vec<(String, String)> {
+        let mut names: Vec<&str> = vec![];
+        for (_, target, _) in self.dangling.iter() {
+            if !names.contains(&target.referent.as_str()) {
+                names.push(&target.referent);
+            }
+        }
+
+        names
+            .into_iter()
+            .enumerate()
+            .map(|(idx, name)| (format!("missing{idx}"), name.to_owned()))
+            .collect()
+    }
+
+    /// Every edge, labeled with its conditions, and the `@CONT`s to scenes that don't exist.
+    fn lines(&self) -> Vec<Line> {
+        let mut lines: Vec<Line> = self
+            .edges
+            .iter()
+            .map(|edge| Line {
+                from: format!("scene{}", edge.from),
+                to: format!("scene{}", edge.to),
+                label: match &edge.kind {
+                    EdgeKind::If(conditions, _) => label(conditions),
+                    EdgeKind::FallThrough | EdgeKind::Cont(_) => None,
+                },
+                falls_through: edge.kind == EdgeKind::FallThrough,
+            })
+            .collect();
+
+        let missing = self.missing();
+        for (from, target, conditions) in self.dangling.iter() {
+            let (id, _) = missing
+                .iter()
+                .find(|(_, name)| *name == target.referent)
+                .unwrap();
+            lines.push(Line {
+                from: format!("scene{from}"),
+                to: id.clone(),
+                label: label(conditions),
+                falls_through: false,
+            });
+        }
+
+        lines
+    }
+}
+
+/// The label of an edge under `conditions`, if there are any.
+fn label(conditions: &[&RichText]) -> Option<String> {
+    let conditions: Vec<_> = conditions
+        .iter()
+        .map(|condition| condition.as_string())
+        .collect();
+    (!conditions.is_empty()).then(|| conditions.join(" and "))
+}
+
+/// Splits text into lines of about `width` characters, between words.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_owned()),
+        }
+    }
+
+    lines
+}
@@ -1,8 +1,19 @@
 pub mod types;
 
+pub mod diagnostic;
+
+pub mod syntax;
+
 #[cfg(feature = "parser")]
 pub mod parser;
 
 pub mod exporter;
 
+pub mod formatter;
+
+pub mod graph;
+
+#[cfg(feature = "parser")]
+pub mod migrate;
+
 pub mod server;
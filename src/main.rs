@@ -1,13 +1,22 @@
 use {
-    crate::{server::LanguageBackend, types::File},
+    crate::{
+        diagnostic::{Diagnostic, Severity},
+        server::LanguageBackend,
+        types::File,
+    },
     clap::{Parser, Subcommand, ValueEnum},
     std::path::PathBuf,
 };
 
+pub mod diagnostic;
 pub mod exporter;
+pub mod formatter;
+pub mod graph;
+pub mod migrate;
 pub mod parser;
 pub mod player;
 pub mod server;
+pub mod syntax;
 pub mod types;
 
 #[tokio::main]
@@ -22,7 +31,7 @@ async fn main() {
                 path,
                 content,
             } => {
-                let file = read_file(&input).unwrap();
+                let file = read_file_recovering(&input);
 
                 let html = match content {
                     PrintContent::All => crate::exporter::export_html(&file),
@@ -37,9 +46,101 @@ async fn main() {
                     }
                 }
             }
-            Commands::Play { input } => {
-                let file = read_file(input).unwrap();
-                player::play(file);
+            Commands::Play { input, session } => {
+                let file = read_file_or_exit(input);
+                let saved = match &session {
+                    Some(path) if path.exists() => {
+                        player::Session::load(path).unwrap_or_else(|error| {
+                            eprintln!("error: couldn't load the session: {error}");
+                            std::process::exit(1)
+                        })
+                    }
+                    _ => player::Session::default(),
+                };
+
+                if let Err(error) = player::play(&file, saved, session.as_deref()) {
+                    eprintln!("error: {error}");
+                    std::process::exit(1);
+                }
+            }
+            Commands::Fmt { input, check } => {
+                let source = std::fs::read_to_string(&input).unwrap_or_else(|error| {
+                    eprint!("{}", FileReadError::IO(error));
+                    std::process::exit(1)
+                });
+                let file = crate::parser::parse(&source).unwrap_or_else(|diagnostic| {
+                    eprint!("{}", diagnostic.render(&source, &input.to_string_lossy()));
+                    std::process::exit(1)
+                });
+                let formatted = formatter::format(&file, migrate::Dialect::of(&source));
+
+                if formatted == source {
+                    return;
+                }
+
+                if check {
+                    eprintln!("{} is not formatted.", input.display());
+                    std::process::exit(1);
+                }
+
+                std::fs::write(&input, formatted).unwrap();
+            }
+            Commands::Check { input } => {
+                let source = std::fs::read_to_string(&input).unwrap_or_else(|error| {
+                    eprint!("{}", FileReadError::IO(error));
+                    std::process::exit(1)
+                });
+                let path = input.to_string_lossy();
+
+                let (file, errors) = crate::parser::parse_recovering(&source);
+                for error in errors.iter() {
+                    eprintln!("{}", error.diagnostic.render(&source, &path));
+                }
+
+                let problems = graph::Graph::new(&file).problems();
+                for problem in problems.iter() {
+                    eprintln!(
+                        "{}",
+                        diagnostic::render(
+                            problem.severity,
+                            &problem.message,
+                            &source,
+                            &path,
+                            &problem.span
+                        )
+                    );
+                }
+
+                let failed = problems
+                    .iter()
+                    .any(|problem| problem.severity == Severity::Error);
+                if failed || !errors.is_empty() {
+                    std::process::exit(1);
+                }
+            }
+            Commands::Graph { input, format } => {
+                let file = read_file_recovering(&input);
+                let graph = graph::Graph::new(&file);
+
+                match format {
+                    GraphFormat::Dot => print!("{}", graph.to_dot()),
+                    GraphFormat::Mermaid => print!("{}", graph.to_mermaid()),
+                }
+            }
+            Commands::Migrate { input, to } => {
+                let source = std::fs::read_to_string(&input).unwrap_or_else(|error| {
+                    eprint!("{}", FileReadError::IO(error));
+                    std::process::exit(1)
+                });
+                let dialect = match to {
+                    MigrateDialect::Bare => migrate::Dialect::Bare,
+                    MigrateDialect::Dashed => migrate::Dialect::Dashed,
+                };
+
+                let migrated = migrate::migrate(&source, dialect);
+                if migrated != source {
+                    std::fs::write(&input, migrated).unwrap();
+                }
             }
             Commands::Serve {} => {
                 start_language_server().await;
@@ -73,10 +174,43 @@ enum Commands {
         #[arg(short, long, default_value = "all")]
         content: PrintContent,
     },
+    /// Rewrites a novel file at <INPUT> in the canonical style.
+    Fmt {
+        #[arg(short, long)]
+        input: PathBuf,
+        /// Only checks whether the file is formatted, failing if it isn't.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Checks a novel file at <INPUT> for problems, failing if it has errors
+    /// or continues to scenes that don't exist.
+    Check {
+        #[arg(short, long)]
+        input: PathBuf,
+    },
+    /// Draws how the scenes of a novel file at <INPUT> lead to each other.
+    Graph {
+        #[arg(short, long)]
+        input: PathBuf,
+        #[arg(short, long, default_value = "dot")]
+        format: GraphFormat,
+    },
+    /// Rewrites a novel file at <INPUT> from one dialect into the other.
+    Migrate {
+        #[arg(short, long)]
+        input: PathBuf,
+        /// The dialect to write it in.
+        #[arg(short, long, default_value = "bare")]
+        to: MigrateDialect,
+    },
     Serve {},
+    /// Plays a novel file at <INPUT> in the terminal.
     Play {
         #[arg(short, long)]
         input: PathBuf,
+        /// A file to pick the playthrough back up from, if it exists, and save it to.
+        #[arg(short, long)]
+        session: Option<PathBuf>,
     },
 }
 
@@ -94,6 +228,24 @@ enum PrintContent {
     Outline,
 }
 
+#[derive(ValueEnum, Clone, Default)]
+enum GraphFormat {
+    /// The DOT language of Graphviz.
+    #[default]
+    Dot,
+    /// A Mermaid flowchart.
+    Mermaid,
+}
+
+#[derive(ValueEnum, Clone, Default)]
+enum MigrateDialect {
+    /// Dialogue lines as they are, and `#TAG text`.
+    #[default]
+    Bare,
+    /// Dialogue lines starting with `- `, and `> TAG: text`.
+    Dashed,
+}
+
 // --- //
 
 /// Starts the novel language server.
@@ -101,7 +253,7 @@ async fn start_language_server() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = tower_lsp::LspService::new(|client| LanguageBackend { client });
+    let (service, socket) = tower_lsp::LspService::new(LanguageBackend::new);
     tower_lsp::Server::new(stdin, stdout, socket)
         .serve(service)
         .await;
@@ -109,12 +261,60 @@ async fn start_language_server() {
 
 pub fn read_file<P: AsRef<std::path::Path>>(path: P) -> Result<File, FileReadError> {
     let raw = std::fs::read_to_string(&path).map_err(FileReadError::IO)?;
-    let (_, file) = crate::parser::file(&raw).map_err(|_| FileReadError::Parse)?;
-    Ok(file)
+    crate::parser::parse(&raw).map_err(|diagnostic| FileReadError::Parse {
+        path: path.as_ref().to_path_buf(),
+        source: raw,
+        diagnostic: Box::new(diagnostic),
+    })
+}
+
+/// Reads a novel file, or reports why it couldn't be read and exits.
+fn read_file_or_exit<P: AsRef<std::path::Path>>(path: P) -> File {
+    read_file(path).unwrap_or_else(|error| {
+        eprint!("{error}");
+        std::process::exit(1)
+    })
+}
+
+/// Reads as much of a novel file as possible, reporting any errors in it on the way.
+fn read_file_recovering<P: AsRef<std::path::Path>>(path: P) -> File {
+    let raw = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+        eprint!("{}", FileReadError::IO(error));
+        std::process::exit(1)
+    });
+
+    let (file, errors) = crate::parser::parse_recovering(&raw);
+    for error in errors {
+        eprintln!(
+            "{}",
+            error
+                .diagnostic
+                .render(&raw, &path.as_ref().to_string_lossy())
+        );
+    }
+
+    file
 }
 
 #[derive(Debug)]
 pub enum FileReadError {
     IO(std::io::Error),
-    Parse,
+    Parse {
+        path: PathBuf,
+        source: String,
+        diagnostic: Box<Diagnostic>,
+    },
+}
+
+impl std::fmt::Display for FileReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileReadError::IO(error) => writeln!(f, "error: {error}"),
+            FileReadError::Parse {
+                path,
+                source,
+                diagnostic,
+            } => write!(f, "{}", diagnostic.render(source, &path.to_string_lossy())),
+        }
+    }
 }
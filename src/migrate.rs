@@ -0,0 +1,180 @@
+//! Rewriting scripts from one dialect of novel into the other.
+//!
+//! Older scripts write dialogue lines starting with `- ` and tagged actions as `> TAG: text`,
+//! while newer ones write dialogue lines as they are and tagged actions as `#TAG text`.
+//! Both parse the same, so rewriting a script only touches how those are written,
+//! and leaves everything else in it, including what couldn't be parsed, as it was.
+
+use crate::{
+    parser::parse_tree_recovering,
+    syntax::{GreenElement, GreenNode, SyntaxKind::*, SyntaxNode},
+};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// Dialogue lines as they are, under their speaker, and tagged actions as `#TAG text`.
+    Bare,
+    /// Dialogue lines starting with `- `, and tagged actions as `> TAG: text`.
+    Dashed,
+}
+
+impl Dialect {
+    /// The dialect a script is written in, going by the first dialogue line or tagged action in it.
+    pub fn of(source: &str) -> Dialect {
+        let (tree, _) = parse_tree_recovering(source);
+        let root = SyntaxNode::new_root(tree);
+
+        for node in root.descendants() {
+            let (dashed, bare) = match node.kind() {
+                DialogueBlock => (Dash, RichText),
+                TaggedAction => (Gt, Hash),
+                _ => continue,
+            };
+            let first = node
+                .children_with_tokens()
+                .map(|child| child.kind())
+                .find(|&kind| kind == dashed || kind == bare);
+
+            match first {
+                Some(kind) if kind == dashed => return Dialect::Dashed,
+                Some(_) => return Dialect::Bare,
+                None => {}
+            }
+        }
+
+        Dialect::Bare
+    }
+}
+
+/// Rewrites a script in `dialect`.
+pub fn migrate(source: &str, dialect: Dialect) -> String {
+    let (tree, _) = parse_tree_recovering(source);
+    rewrite(&tree, dialect).text()
+}
+
+fn rewrite(node: &GreenNode, dialect: Dialect) -> GreenNode {
+    let children = node
+        .children()
+        .iter()
+        .map(|child| match child {
+            GreenElement::Node(child) => GreenElement::Node(Arc::new(rewrite(child, dialect))),
+            token => token.clone(),
+        })
+        .collect();
+
+    let children = match node.kind() {
+        DialogueBlock => dialogue_block(children, dialect),
+        TaggedAction => tagged_action(children, dialect),
+        _ => children,
+    };
+
+    GreenNode::new(node.kind(), children)
+}
+
+fn dialogue_block(children: Vec<GreenElement>, dialect: Dialect) -> Vec<GreenElement> {
+    let mut rewritten = vec![];
+
+    // Lines without a speaker before them continue what the last speaker said,
+    // which has to be written as `[&]` without the dashes.
+    if dialect == Dialect::Bare && children.first().map(GreenElement::kind) == Some(Dash) {
+        let line_ending = children
+            .iter()
+            .find(|child| child.kind() == Newline)
+            .map_or("\n".to_owned(), text);
+
+        rewritten.extend([
+            GreenElement::token(LBracket, "["),
+            GreenElement::node(Reference, vec![GreenElement::token(Referent, "&")]),
+            GreenElement::token(RBracket, "]"),
+            GreenElement::token(Newline, &line_ending),
+        ]);
+    }
+
+    let mut children = children.into_iter().peekable();
+    let mut dashed = false;
+    while let Some(child) = children.next() {
+        match (child.kind(), dialect) {
+            (Dash, Dialect::Bare) => {
+                let whitespace = children.next_if(|child| child.kind() == Whitespace);
+
+                // A line that would start with `- ` without its dash keeps it.
+                if children
+                    .peek()
+                    .is_some_and(|line| starts_with_dash(&text(line)))
+                {
+                    rewritten.push(child);
+                    rewritten.extend(whitespace);
+                }
+            }
+            (Dash, Dialect::Dashed) => {
+                dashed = true;
+                rewritten.push(child);
+            }
+            (RichText, Dialect::Dashed) => {
+                if !std::mem::take(&mut dashed) {
+                    rewritten.push(GreenElement::token(Dash, "-"));
+                    rewritten.push(GreenElement::token(Whitespace, " "));
+                }
+                rewritten.push(child);
+            }
+            _ => rewritten.push(child),
+        }
+    }
+
+    rewritten
+}
+
+fn starts_with_dash(line: &str) -> bool {
+    line.strip_prefix('-')
+        .is_some_and(|rest| rest.starts_with([' ', '\t']))
+}
+
+fn tagged_action(children: Vec<GreenElement>, dialect: Dialect) -> Vec<GreenElement> {
+    let marker = match dialect {
+        Dialect::Bare => Hash,
+        Dialect::Dashed => Gt,
+    };
+    if children.iter().any(|child| child.kind() == marker) {
+        return children;
+    }
+
+    let position = |kind| children.iter().position(|child| child.kind() == kind);
+    let (Some(tag), Some(text)) = (position(Ident), position(RichText)) else {
+        return children;
+    };
+
+    let leading = children
+        .iter()
+        .take_while(|child| child.kind() == Whitespace)
+        .cloned();
+    let separator = match &children[text - 1] {
+        whitespace if whitespace.kind() == Whitespace => whitespace.clone(),
+        _ => GreenElement::token(Whitespace, " "),
+    };
+    let tag = children[tag].clone();
+
+    let written = match dialect {
+        Dialect::Bare => vec![GreenElement::token(Hash, "#"), tag, separator],
+        Dialect::Dashed => vec![
+            GreenElement::token(Gt, ">"),
+            GreenElement::token(Whitespace, " "),
+            tag,
+            GreenElement::token(Colon, ":"),
+            separator,
+        ],
+    };
+
+    leading
+        .chain(written)
+        .chain(children[text..].iter().cloned())
+        .collect()
+}
+
+/// The source an element was parsed from.
+fn text(element: &GreenElement) -> String {
+    match element {
+        GreenElement::Node(node) => node.text(),
+        GreenElement::Token(token) => token.text().to_owned(),
+    }
+}
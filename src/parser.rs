@@ -1,152 +1,1053 @@
 use {
-    crate::types::*,
+    crate::{
+        diagnostic::Diagnostic,
+        syntax::{
+            GreenElement, GreenNode,
+            SyntaxKind::{self, *},
+            SyntaxNode, lower,
+        },
+        types::{self, Span},
+    },
     nom::{
-        IResult, Parser,
+        IResult, Input as _, Parser,
         branch::alt,
-        bytes::{complete::take_until1, tag},
-        character::{
-            anychar,
-            complete::{alphanumeric1, line_ending, multispace0, newline, none_of, space0, space1},
+        bytes::{
+            complete::{take, take_till1, take_while1},
+            tag,
         },
-        combinator::{complete, eof, map, opt},
-        multi::{many_till, many1, separated_list0, separated_list1},
-        sequence::{delimited, preceded, terminated},
+        character::complete::{
+            alphanumeric1, digit0, digit1, line_ending, multispace0, multispace1, newline, none_of,
+            not_line_ending, one_of, satisfy, space0, space1,
+        },
+        combinator::{all_consuming, eof, map, not, opt, peek, recognize, verify},
+        error::{ContextError, ErrorKind, ParseError, context},
+        multi::{many0, many1},
+        sequence::{preceded, terminated},
     },
-    std::collections::HashMap,
+    nom_locate::LocatedSpan,
+    std::{cell::RefCell, collections::HashMap, ops::Range, sync::Arc},
 };
 
-pub fn file(input: &str) -> IResult<&str, File> {
-    let parser = terminated(
-        (
-            opt(key_value_list),
-            separated_list0(
-                multispace0,
-                complete(delimited(multispace0, scene, multispace0)),
-            ),
-        ),
-        multispace0,
-    );
+mod incremental;
 
-    map(parser, |(frontmatter, scenes)| File {
-        frontmatter,
-        scenes,
+pub use incremental::{Edit, PendingShifts, reparse, reparse_with};
+
+/// Parses a whole novel file, failing with a [`Diagnostic`] at the first thing that couldn't be parsed.
+pub fn parse(source: &str) -> Result<types::File, Diagnostic> {
+    parse_tree(source).map(|tree| lower(&SyntaxNode::new_root(tree)))
+}
+
+/// Parses the syntax tree of a whole novel file, failing like [`parse`] does.
+pub fn parse_tree(source: &str) -> Result<Arc<GreenNode>, Diagnostic> {
+    match file(Input::new(source)) {
+        Ok((rest, file)) if rest.is_empty() => Ok(file),
+        Ok((rest, _)) => Err(diagnostic(source, diagnose(rest))),
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => Err(diagnostic(source, error)),
+        Err(nom::Err::Incomplete(_)) => Err(Diagnostic::new(source, source.len(), vec![])),
+    }
+}
+
+/// Finds out why parsing stopped at `rest`, by parsing it again as either a scene or a scene item.
+fn diagnose(rest: Input<'_>) -> SyntaxError<'_> {
+    let scene_error = match scene(rest) {
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => error,
+        _ => SyntaxError::expected(rest, "scene header"),
+    };
+
+    match scene_item(rest) {
+        Err(nom::Err::Error(error) | nom::Err::Failure(error)) => scene_error.or(error),
+        _ => scene_error,
+    }
+}
+
+/// Parses as much of a novel file as possible, for when it's still being written.
+///
+/// Whenever something can't be parsed, it is reported and skipped until the next
+/// scene header or blank line, where parsing resumes. Scene items found after
+/// a skipped region are added to the scene before it.
+pub fn parse_recovering(source: &str) -> (types::File, Vec<RecoveredError>) {
+    let (tree, errors) = parse_tree_recovering(source);
+    (lower(&SyntaxNode::new_root(tree)), errors)
+}
+
+/// Parses the syntax tree of a novel file like [`parse_recovering`] does.
+///
+/// The skipped regions are kept in the tree as [`Error`](crate::syntax::SyntaxKind::Error) tokens.
+pub fn parse_tree_recovering(source: &str) -> (Arc<GreenNode>, Vec<RecoveredError>) {
+    let input = Input::new(source);
+    let (rest, frontmatter) = frontmatter.parse_complete(input).unwrap_or((input, vec![]));
+
+    let mut recovery = Recovery::new(source);
+    recovery.children.extend(frontmatter);
+    recovery.run(rest, |_| false);
+    let (children, errors) = recovery.finish();
+
+    (Arc::new(GreenNode::new(File, children)), errors)
+}
+
+/// Where [`parse_tree_recovering`] is at in a source.
+struct Recovery<'a> {
+    source: &'a str,
+    /// The children of the file so far.
+    children: Vec<GreenElement>,
+    /// The last scene, which the items found after it are added to.
+    last_scene: Option<Vec<GreenElement>>,
+    /// Whitespace and skipped regions since the last scene or item.
+    pending: Vec<GreenElement>,
+    errors: Vec<RecoveredError>,
+}
+
+impl<'a> Recovery<'a> {
+    fn new(source: &'a str) -> Self {
+        Recovery {
+            source,
+            children: vec![],
+            last_scene: None,
+            pending: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Parses from `rest` to the end of the source, unless `stop` says to stop at the offset
+    /// of something before parsing it. Returns where it stopped.
+    fn run(&mut self, mut rest: Input<'a>, stop: impl Fn(usize) -> bool) -> Input<'a> {
+        let mut skipping = false;
+
+        loop {
+            let whitespace = skip_whitespace(rest);
+            self.pending
+                .extend(green_token(Whitespace, span_input(rest, whitespace)));
+            rest = whitespace;
+            if rest.is_empty() || stop(rest.location_offset()) {
+                return rest;
+            }
+
+            if let Ok((next, GreenElement::Node(scene))) = scene(rest) {
+                self.start_scene(scene.children().to_vec());
+                rest = next;
+            } else if let Ok((next, item)) = scene_item(rest) {
+                match self.last_scene.as_mut() {
+                    Some(scene) => {
+                        scene.append(&mut self.pending);
+                        scene.push(item);
+                    }
+                    // Items before the first scene header are a scene of their own.
+                    None => self.start_scene(vec![item]),
+                }
+                rest = next;
+            } else if let Ok((next, header)) = unterminated_scene_header(rest) {
+                self.errors.push(RecoveredError {
+                    diagnostic: diagnostic(self.source, diagnose(rest)),
+                    skipped: span(rest, rest),
+                });
+                self.start_scene(header);
+                rest = next;
+            } else {
+                let next = resync(rest);
+                let skipped = span(rest, next);
+                self.pending
+                    .extend(green_token(Error, span_input(rest, next)));
+
+                // Before the first scene, everything up to it is skipped as one.
+                match self.errors.last_mut() {
+                    Some(error) if skipping && self.last_scene.is_none() => {
+                        error.skipped.end = skipped.end
+                    }
+                    _ => self.errors.push(RecoveredError {
+                        diagnostic: diagnostic(self.source, diagnose(rest)),
+                        skipped,
+                    }),
+                }
+
+                rest = next;
+                skipping = true;
+                continue;
+            }
+
+            skipping = false;
+        }
+    }
+
+    fn start_scene(&mut self, children: Vec<GreenElement>) {
+        self.close_scene();
+        self.children.append(&mut self.pending);
+        self.last_scene = Some(children);
+    }
+
+    fn close_scene(&mut self) {
+        if let Some(scene) = self.last_scene.take() {
+            self.children.push(GreenElement::node(Scene, scene));
+        }
+    }
+
+    /// The children of the file and the errors found in it.
+    fn finish(mut self) -> (Vec<GreenElement>, Vec<RecoveredError>) {
+        self.close_scene();
+        self.children.append(&mut self.pending);
+        (self.children, self.errors)
+    }
+}
+
+/// A syntax error [`parse_recovering`] got past.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RecoveredError {
+    pub diagnostic: Diagnostic,
+    /// The byte range of the source that was skipped because of the error, which may be empty.
+    pub skipped: Range<usize>,
+}
+
+/// The input after any whitespace at its start.
+fn skip_whitespace(input: Input<'_>) -> Input<'_> {
+    let whitespace = input.fragment().len() - input.fragment().trim_start().len();
+    input.take_from(whitespace)
+}
+
+/// Skips to the next line that is blank or starts a scene, where parsing can resume.
+fn resync(input: Input<'_>) -> Input<'_> {
+    let mut rest = input;
+
+    loop {
+        rest = match rest.fragment().find('\n') {
+            Some(idx) => rest.take_from(idx + 1),
+            None => return rest.take_from(rest.len()),
+        };
+
+        let line = rest.fragment().lines().next().unwrap_or("");
+        if line.trim().is_empty() || line.starts_with("==") {
+            return rest;
+        }
+    }
+}
+
+fn diagnostic(source: &str, error: SyntaxError<'_>) -> Diagnostic {
+    let expected = error.expected.iter().map(|e| e.to_string()).collect();
+    Diagnostic::new(source, error.input.location_offset(), expected)
+}
+
+/// The input of the parsers in this module, which keeps track of where it is in the source.
+pub type Input<'a> = LocatedSpan<&'a str>;
+
+pub type ParseResult<'a, O> = IResult<Input<'a>, O, SyntaxError<'a>>;
+
+/// The error produced by the parsers in this module.
+///
+/// When alternatives fail, the one that got furthest into the input wins,
+/// since it's most likely what the writer meant.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxError<'a> {
+    /// The input remaining where parsing failed.
+    pub input: Input<'a>,
+    /// Descriptions of what could have been parsed at `input`.
+    pub expected: Vec<&'static str>,
+}
+
+impl<'a> SyntaxError<'a> {
+    fn expected(input: Input<'a>, expected: &'static str) -> Self {
+        SyntaxError {
+            input,
+            expected: vec![expected],
+        }
+    }
+}
+
+impl<'a> ParseError<Input<'a>> for SyntaxError<'a> {
+    fn from_error_kind(input: Input<'a>, _: ErrorKind) -> Self {
+        SyntaxError {
+            input,
+            expected: vec![],
+        }
+    }
+
+    fn append(_: Input<'a>, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        let offset = self.input.location_offset();
+        match offset.cmp(&other.input.location_offset()) {
+            std::cmp::Ordering::Greater => self,
+            std::cmp::Ordering::Less => other,
+            std::cmp::Ordering::Equal => {
+                for expected in other.expected {
+                    if !self.expected.contains(&expected) {
+                        self.expected.push(expected);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<'a> ContextError<Input<'a>> for SyntaxError<'a> {
+    fn add_context(input: Input<'a>, context: &'static str, other: Self) -> Self {
+        // The context only describes the error if it happened before anything was parsed.
+        if other.input.location_offset() == input.location_offset() {
+            return SyntaxError::expected(input, context);
+        }
+
+        other
+    }
+}
+
+/// The span between the starts of two inputs.
+fn span(from: Input<'_>, to: Input<'_>) -> Span {
+    from.location_offset()..to.location_offset()
+}
+
+/// The part of `from` before `to`.
+fn span_input<'a>(from: Input<'a>, to: Input<'a>) -> Input<'a> {
+    from.take(to.location_offset() - from.location_offset())
+}
+
+/// Parts of the syntax tree that can be put together as the children of a node.
+trait IntoElements {
+    fn push_into(self, elements: &mut Vec<GreenElement>);
+}
+
+impl IntoElements for GreenElement {
+    fn push_into(self, elements: &mut Vec<GreenElement>) {
+        elements.push(self);
+    }
+}
+
+impl<T: IntoElements> IntoElements for Option<T> {
+    fn push_into(self, elements: &mut Vec<GreenElement>) {
+        if let Some(element) = self {
+            element.push_into(elements);
+        }
+    }
+}
+
+impl<T: IntoElements> IntoElements for Vec<T> {
+    fn push_into(self, elements: &mut Vec<GreenElement>) {
+        for element in self {
+            element.push_into(elements);
+        }
+    }
+}
+
+macro_rules! impl_into_elements {
+    ($($name:ident),+) => {
+        impl<$($name: IntoElements),+> IntoElements for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn push_into(self, elements: &mut Vec<GreenElement>) {
+                let ($($name,)+) = self;
+                $($name.push_into(elements);)+
+            }
+        }
+    };
+}
+
+impl_into_elements!(A, B);
+impl_into_elements!(A, B, C);
+impl_into_elements!(A, B, C, D);
+impl_into_elements!(A, B, C, D, E);
+impl_into_elements!(A, B, C, D, E, F);
+impl_into_elements!(A, B, C, D, E, F, G);
+impl_into_elements!(A, B, C, D, E, F, G, H);
+
+fn elements(parts: impl IntoElements) -> Vec<GreenElement> {
+    let mut elements = vec![];
+    parts.push_into(&mut elements);
+    elements
+}
+
+/// A node of the given kind, with whatever `parser` parses as its children.
+fn node<'a, O: IntoElements>(
+    kind: SyntaxKind,
+    parser: impl Parser<Input<'a>, Output = O, Error = SyntaxError<'a>>,
+) -> impl Parser<Input<'a>, Output = GreenElement, Error = SyntaxError<'a>> {
+    map(parser, move |children| {
+        GreenElement::node(kind, elements(children))
+    })
+}
+
+/// A token of the given kind, with the text `parser` consumed.
+fn token<'a, O>(
+    kind: SyntaxKind,
+    parser: impl Parser<Input<'a>, Output = O, Error = SyntaxError<'a>>,
+) -> impl Parser<Input<'a>, Output = GreenElement, Error = SyntaxError<'a>> {
+    map(recognize(parser), move |text: Input| {
+        GreenElement::token(kind, text.fragment())
+    })
+}
+
+/// A whitespace token with the text `parser` consumed, if it consumed any.
+fn whitespace<'a, O>(
+    parser: impl Parser<Input<'a>, Output = O, Error = SyntaxError<'a>>,
+) -> impl Parser<Input<'a>, Output = Option<GreenElement>, Error = SyntaxError<'a>> {
+    map(recognize(parser), |text: Input| {
+        green_token(Whitespace, text)
     })
-    .parse(input)
 }
 
-fn key_value_list(input: &str) -> IResult<&str, HashMap<String, Value>> {
-    let kv_entry = map(
-        (identifier, space0, tag(":"), space0, value),
-        |(id, _, _, _, value)| (id, value),
+/// A token with the text of `input`, unless it is empty.
+fn green_token(kind: SyntaxKind, input: Input<'_>) -> Option<GreenElement> {
+    (!input.is_empty()).then(|| GreenElement::token(kind, input.fragment()))
+}
+
+/// A token of the given kind for the text of `input` without surrounding whitespace,
+/// between tokens for the whitespace.
+fn trimmed(kind: SyntaxKind, input: Input<'_>) -> Vec<GreenElement> {
+    let text = input.fragment();
+    let start = text.len() - text.trim_start().len();
+    let end = text.trim_end().len().max(start);
+
+    elements((
+        green_token(Whitespace, input.take(start)),
+        GreenElement::token(kind, &text[start..end]),
+        green_token(Whitespace, input.take_from(end)),
+    ))
+}
+
+pub fn file(input: Input<'_>) -> ParseResult<'_, Arc<GreenNode>> {
+    let parser = (
+        frontmatter,
+        opt((whitespace(multispace0), headerless_scene)),
+        many0(alt((token(Whitespace, multispace1), scene))),
     );
 
-    map(separated_list0(newline, kv_entry), |entries| {
-        entries.into_iter().collect::<HashMap<_, _>>()
+    map(parser, |children| {
+        Arc::new(GreenNode::new(File, elements(children)))
     })
-    .parse(input)
+    .parse_complete(input)
 }
 
-fn value(input: &str) -> IResult<&str, Value> {
-    alt((map(rich_text, Value::RichText),)).parse(input)
+/// Whatever describes a file before its first scene, all of which is optional:
+/// metadata between `---` fences, more metadata without them, then the file's title.
+fn frontmatter(input: Input<'_>) -> ParseResult<'_, Vec<GreenElement>> {
+    let parser = (
+        opt(fenced_frontmatter),
+        opt((whitespace(multispace0), key_value_list)),
+        opt((whitespace(multispace0), title_block)),
+    );
+
+    map(parser, elements).parse_complete(input)
 }
 
-fn scene(input: &str) -> IResult<&str, Scene> {
-    let header = delimited(
-        tag("=="),
-        opt(map(take_until1("=="), |name: &str| name.trim().to_owned())),
-        (tag("=="), line_ending),
+/// ```text
+/// ---
+/// myprop: 123
+/// ---
+/// ```
+fn fenced_frontmatter(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        token(Fence, tag("---")),
+        whitespace(space0),
+        token(Newline, line_ending),
+        whitespace(multispace0),
+        opt(key_value_list),
+        whitespace(multispace0),
+        token(Fence, context("`---`", tag("---"))),
+        whitespace(space0),
     );
 
-    let meta = key_value_list;
+    node(Frontmatter, parser).parse_complete(input)
+}
 
-    let items = separated_list1((newline, multispace0), scene_item);
+/// The title of a file on a line of its own, maybe followed by its subtitle on the next one.
+fn title_block(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let line = || {
+        preceded(
+            not(alt((tag("---"), recognize(kv_entry)))),
+            node(Value, rich_text_line),
+        )
+    };
 
-    let parser = (header, meta, multispace0, items);
+    node(
+        TitleBlock,
+        (line(), opt((token(Newline, line_ending), line()))),
+    )
+    .parse_complete(input)
+}
 
-    map(parser, |(name, meta, _, items)| Scene { name, meta, items }).parse(input)
+/// Metadata entries, one per line.
+fn key_value_list(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let entries = (kv_entry, many0((token(Newline, newline), kv_entry)));
+    node(Meta, entries).parse_complete(input)
 }
 
-fn scene_item(input: &str) -> IResult<&str, SceneItem> {
-    alt((comment, tagged_action_line, dialogue_block, action_block)).parse(input)
+fn kv_entry(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        identifier,
+        whitespace(space0),
+        token(Colon, tag(":")),
+        whitespace(space0),
+        value,
+        whitespace(space0),
+    );
+
+    node(MetaEntry, parser).parse_complete(input)
 }
 
-fn action_block(input: &str) -> IResult<&str, SceneItem> {
-    map(rich_text_block, |lines| {
-        SceneItem::ActionBlock(lines.into_iter().fold(RichText(vec![]), RichText::merge))
-    })
-    .parse(input)
+/// A value, which is typed when all of it is either a typed value or a list of them,
+/// and rich text otherwise.
+fn value(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let value_end = || peek((space0, alt((line_ending, eof))));
+
+    alt((
+        terminated(list, value_end()),
+        terminated(node(Value, typed_value), value_end()),
+        node(Value, rich_text),
+    ))
+    .parse_complete(input)
+}
+
+/// Whether `text`, as the whole of a value, would be parsed as a typed value rather than as rich text.
+pub(crate) fn is_typed_value(text: &str) -> bool {
+    let typed = alt((recognize(list), recognize(typed_value)));
+    all_consuming(terminated(typed, space0))
+        .parse_complete(Input::new(text))
+        .is_ok()
+}
+
+/// `a, b` or `[Claire], [Andy]`, where each item is a typed value or a single word.
+fn list(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let item_end = || peek((space0, alt((tag(","), line_ending, eof))));
+    let item = || {
+        let word = map(word, |word| vec![word]);
+        node(Value, terminated(alt((typed_value, word)), item_end()))
+    };
+    let separator = (
+        whitespace(space0),
+        token(Comma, tag(",")),
+        whitespace(space0),
+    );
+
+    node(Value, (item(), many1((separator, item())))).parse_complete(input)
+}
+
+fn typed_value(input: Input<'_>) -> ParseResult<'_, Vec<GreenElement>> {
+    let number = recognize((
+        opt(tag("-")),
+        alt((tag("0"), recognize((one_of("123456789"), digit0)))),
+        opt((tag("."), digit1)),
+    ));
+    let boolean = alt((tag("true"), tag("false")));
+    let date = verify(
+        take_while1(|c: char| c.is_ascii_digit() || c == '-'),
+        |date: &Input| types::Date::parse(date.fragment()).is_some(),
+    );
+    let time = verify(
+        take_while1(|c: char| c.is_ascii_digit() || c == ':'),
+        |time: &Input| types::Time::parse(time.fragment()).is_some(),
+    );
+    let duration = recognize((
+        digit1,
+        one_of("hms"),
+        many0((space0, digit1, one_of("hms"))),
+    ));
+    let reference = (
+        preceded(not(tag("[[")), token(LBracket, tag("["))),
+        reference,
+        token(RBracket, context("`]`", tag("]"))),
+    );
+
+    alt((
+        map(token(Date, date), |date| vec![date]),
+        map(token(Time, time), |time| vec![time]),
+        map(token(Duration, duration), |duration| vec![duration]),
+        map(token(Number, number), |number| vec![number]),
+        map(token(Boolean, boolean), |boolean| vec![boolean]),
+        map(reference, elements),
+    ))
+    .parse_complete(input)
+}
+
+/// Rich text without any whitespace or punctuation in it, like a tag.
+fn word(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let text = token(Text, many1(none_of("\r\n \t=[]*_~\\,.!?;:\"(){}")));
+    let parts = many1(alt((
+        rich_text_part_reference,
+        rich_text_part_escaped,
+        text,
+    )));
+
+    node(RichText, parts).parse_complete(input)
+}
+
+fn scene(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        scene_header,
+        opt(key_value_list),
+        opt((whitespace(multispace0), scene_items)),
+    );
+
+    node(Scene, parser).parse_complete(input)
 }
 
-fn tagged_action_line(input: &str) -> IResult<&str, SceneItem> {
+/// Items before the first scene header, which older scripts start with, as a scene of their own.
+fn headerless_scene(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    node(Scene, scene_items).parse_complete(input)
+}
+
+fn scene_items(input: Input<'_>) -> ParseResult<'_, Vec<GreenElement>> {
+    let items = (
+        scene_item,
+        many0((whitespace((newline, multispace0)), scene_item)),
+    );
+
+    map(items, elements).parse_complete(input)
+}
+
+/// `== Name ==`, on a line of its own.
+fn scene_header(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let name = map(
+        recognize(many1(preceded(not(tag("==")), none_of("\r\n")))),
+        |name| trimmed(Name, name),
+    );
+
+    let parser = (
+        token(Equals2, context("`==`", tag("=="))),
+        opt(name),
+        token(Equals2, context("`==`", tag("=="))),
+        whitespace(space0),
+        token(Newline, line_ending),
+    );
+
+    node(SceneHeader, parser).parse_complete(input)
+}
+
+/// A scene header that isn't closed with `==`, like `== Scene 01`, for error recovery.
+///
+/// Gives the children of a scene with no items, with whatever comes after the name as an error.
+fn unterminated_scene_header(input: Input<'_>) -> ParseResult<'_, Vec<GreenElement>> {
+    let name = map(not_line_ending, |line: Input| {
+        let text = line.fragment();
+        let start = text.len() - text.trim_start().len();
+        let end = text
+            .trim_end()
+            .trim_end_matches('=')
+            .trim_end()
+            .len()
+            .max(start);
+        let rest = line.take_from(end);
+        let rest_kind = match rest.fragment().trim().is_empty() {
+            true => Whitespace,
+            false => Error,
+        };
+
+        elements((
+            green_token(Whitespace, line.take(start)),
+            green_token(Name, line.take(end).take_from(start)),
+            green_token(rest_kind, rest),
+        ))
+    });
+
+    let header = node(
+        SceneHeader,
+        (
+            token(Equals2, tag("==")),
+            name,
+            opt(token(Newline, line_ending)),
+        ),
+    );
+
+    map((header, opt(key_value_list)), elements).parse_complete(input)
+}
+
+fn scene_item(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    alt((
+        directive,
+        comment,
+        tagged_action_line,
+        dialogue_block,
+        action_block,
+    ))
+    .parse_complete(input)
+}
+
+fn directive(input: Input<'_>) -> ParseResult<'_, GreenElement> {
     map(
-        delimited(space0, (tag("#"), identifier, space1, rich_text), space0),
-        |(_, tag, _, rich_text)| SceneItem::TaggedAction(tag, rich_text),
+        (
+            whitespace(space0),
+            token(At, tag("@")),
+            alt((directive_cont, directive_if, directive_set)),
+        ),
+        |(whitespace, at, (kind, rest))| GreenElement::node(kind, elements((whitespace, at, rest))),
+    )
+    .parse_complete(input)
+}
+
+/// `@CONT Scene Name`
+fn directive_cont(input: Input<'_>) -> ParseResult<'_, (SyntaxKind, Vec<GreenElement>)> {
+    map(
+        (
+            token(Keyword, context("`CONT`", tag("CONT"))),
+            whitespace(space1),
+            reference,
+        ),
+        |(keyword, whitespace, target)| (Cont, elements((keyword, whitespace, trim_end(target)))),
     )
-    .parse(input)
+    .parse_complete(input)
+}
+
+/// Splits the whitespace at the end of a reference off into a token after it.
+fn trim_end(reference: GreenElement) -> Vec<GreenElement> {
+    let GreenElement::Node(node) = &reference else {
+        return vec![reference];
+    };
+    let Some(GreenElement::Token(last)) = node.children().last() else {
+        return vec![reference];
+    };
+
+    let text = last.text().trim_end();
+    if text.len() == last.text().len() {
+        return vec![reference];
+    }
+
+    let count = node.children().len();
+    let trimmed = node.splice_children(count - 1..count, [GreenElement::token(last.kind(), text)]);
+
+    vec![
+        GreenElement::Node(Arc::new(trimmed)),
+        GreenElement::token(Whitespace, &last.text()[text.len()..]),
+    ]
 }
 
-fn comment(input: &str) -> IResult<&str, SceneItem> {
+/// `@IF condition { ... }` or, inline, `@IF condition @DIRECTIVE ...`
+fn directive_if(input: Input<'_>) -> ParseResult<'_, (SyntaxKind, Vec<GreenElement>)> {
+    let body = alt((directive_block, directive));
+
     map(
-        (delimited(space0, tag("//"), space0), rich_text),
-        |(_, rich_text)| SceneItem::SpoilerBlock(rich_text),
+        (
+            token(Keyword, context("`IF`", tag("IF"))),
+            whitespace(space1),
+            condition,
+            whitespace(space0),
+            body,
+        ),
+        |children| (If, elements(children)),
     )
-    .parse(input)
+    .parse_complete(input)
+}
+
+/// The condition of an `@IF`, up to the directive or block it guards.
+fn condition(input: Input<'_>) -> ParseResult<'_, Vec<GreenElement>> {
+    let (rest, text) =
+        take_till1(|c| matches!(c, '@' | '{' | '\r' | '\n')).parse_complete(input)?;
+    let (trailing, text) = take(text.fragment().trim_end().len()).parse_complete(text)?;
+    let (_, condition) = all_consuming(rich_text).parse_complete(text)?;
+
+    Ok((
+        rest,
+        elements((condition, green_token(Whitespace, trailing))),
+    ))
 }
 
-fn dialogue_block(input: &str) -> IResult<&str, SceneItem> {
+fn directive_block(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let item = || preceded(not(tag("}")), scene_item);
+    let items = (item(), many0((whitespace((newline, multispace0)), item())));
+
+    let parser = (
+        token(LBrace, tag("{")),
+        whitespace(space0),
+        token(Newline, line_ending),
+        whitespace(multispace0),
+        opt(items),
+        whitespace(multispace0),
+        token(RBrace, context("`}`", tag("}"))),
+    );
+
+    node(Block, parser).parse_complete(input)
+}
+
+/// `@SET Flag Name` or `@SET Variable = value`
+fn directive_set(input: Input<'_>) -> ParseResult<'_, (SyntaxKind, Vec<GreenElement>)> {
+    let name = map(take_till1(|c| matches!(c, '=' | '\r' | '\n')), |name| {
+        trimmed(Name, name)
+    });
+
     map(
         (
-            delimited(tag("["), reference, tag("]")),
-            preceded(line_ending, rich_text_block),
+            token(Keyword, context("`SET`", tag("SET"))),
+            whitespace(space1),
+            name,
+            opt((token(Equals, tag("=")), whitespace(space0), value)),
+            whitespace(space0),
         ),
-        |(speaker, block)| SceneItem::DialogueBlock { speaker, block },
+        |children| (Set, elements(children)),
     )
-    .parse(input)
+    .parse_complete(input)
+}
+
+/// Lines of rich text, keeping the line breaks between them.
+///
+/// A line of dialogue starting with `- ` or a tagged action ends the block.
+fn action_block(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        rich_text_line,
+        many0((
+            token(Newline, line_ending),
+            preceded(
+                not(alt((recognize(dash), recognize(tagged_action_line)))),
+                rich_text_line,
+            ),
+        )),
+    );
+
+    node(ActionBlock, parser).parse_complete(input)
+}
+
+fn tagged_action_line(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    alt((tagged_action_hash, tagged_action_gt)).parse_complete(input)
+}
+
+/// `#TAG text`
+fn tagged_action_hash(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        whitespace(space0),
+        token(Hash, tag("#")),
+        identifier,
+        whitespace(space1),
+        rich_text,
+        whitespace(space0),
+    );
+
+    node(TaggedAction, parser).parse_complete(input)
 }
 
-fn rich_text_block(input: &str) -> IResult<&str, Vec<RichText>> {
-    separated_list0(line_ending, rich_text).parse(input)
+/// `> TAG: text`
+fn tagged_action_gt(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        (whitespace(space0), token(Gt, tag(">")), whitespace(space0)),
+        identifier,
+        whitespace(space0),
+        token(Colon, tag(":")),
+        whitespace(space0),
+        rich_text,
+        whitespace(space0),
+    );
+
+    node(TaggedAction, parser).parse_complete(input)
+}
+
+fn comment(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        whitespace(space0),
+        token(Slash2, tag("//")),
+        whitespace(space0),
+        rich_text,
+    );
+
+    node(SpoilerBlock, parser).parse_complete(input)
 }
 
-fn rich_text(input: &str) -> IResult<&str, RichText> {
-    let parser = many1(complete(alt((
+/// A speaker in brackets on a line of its own, then the lines they say, which may start with `- `.
+///
+/// Lines starting with `- ` without a speaker before them continue what the last speaker said.
+/// A tagged action ends the block.
+fn dialogue_block(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let line = || preceded(not(tagged_action_line), (opt(dash), rich_text_line));
+    let lines = (line(), many0((token(Newline, line_ending), line())));
+
+    let with_speaker = (
+        preceded(not(tag("[[")), token(LBracket, tag("["))),
+        reference,
+        token(RBracket, context("`]`", tag("]"))),
+        token(Newline, line_ending),
+        opt(lines),
+    );
+    let dashed = (
+        (dash, rich_text_line),
+        many0((token(Newline, line_ending), (dash, rich_text_line))),
+    );
+
+    alt((
+        node(DialogueBlock, with_speaker),
+        node(DialogueBlock, dashed),
+    ))
+    .parse_complete(input)
+}
+
+/// The `- ` before a line of dialogue.
+fn dash(input: Input<'_>) -> ParseResult<'_, Vec<GreenElement>> {
+    map((token(Dash, tag("-")), whitespace(space1)), elements).parse_complete(input)
+}
+
+/// A line of rich text, which may not open a directive or close a directive block.
+fn rich_text_line(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    preceded(not((space0, one_of("@}"))), rich_text).parse_complete(input)
+}
+
+fn rich_text(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let sections = Sections::default();
+    node(RichText, many1(|input| rich_text_part(input, &sections))).parse_complete(input)
+}
+
+/// The formatted sections already tried in some rich text, by where they start and their delimiter.
+///
+/// A line with many markers can be split into sections in many ways, and without remembering
+/// what was tried, each of them would parse the rest of the line all over again.
+type Sections<'a> = RefCell<HashMap<(usize, &'static str), ParseResult<'a, GreenElement>>>;
+
+fn rich_text_part<'a>(input: Input<'a>, sections: &Sections<'a>) -> ParseResult<'a, GreenElement> {
+    alt((
         rich_text_part_reference,
+        rich_text_part_variable,
+        |input| rich_text_part_formatted(input, sections),
+        |input| rich_text_part_tagged(input, sections),
+        rich_text_part_escaped,
         rich_text_part_text,
-    ))));
-    map(parser, RichText).parse(input)
+        rich_text_part_marker,
+    ))
+    .parse_complete(input)
 }
 
-fn rich_text_part_reference(input: &str) -> IResult<&str, RichTextPart> {
-    let parser = delimited(tag("[["), reference, tag("]]"));
-    map(parser, RichTextPart::Reference).parse(input)
+fn rich_text_part_reference(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let parser = (
+        token(LBracket2, tag("[[")),
+        reference,
+        token(RBracket2, context("`]]`", tag("]]"))),
+    );
+
+    node(Link, parser).parse_complete(input)
 }
 
-fn rich_text_part_text(input: &str) -> IResult<&str, RichTextPart> {
-    let parser = many1(none_of("\r\n=[]"));
-    map(parser, |text| {
-        RichTextPart::Text(text.iter().collect::<String>())
-    })
-    .parse(input)
+/// `{Name}`, which is replaced by the value of a variable, or a pronoun like `{they}`.
+fn rich_text_part_variable(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let name = verify(
+        take_while1(|c: char| c.is_alphanumeric() || matches!(c, ' ' | '_')),
+        |name: &Input| name.fragment().trim() == *name.fragment(),
+    );
+
+    node(
+        Variable,
+        (
+            token(LBrace, tag("{")),
+            token(Name, name),
+            token(RBrace, tag("}")),
+        ),
+    )
+    .parse_complete(input)
 }
 
-fn reference(input: &str) -> IResult<&str, Reference> {
-    let referent = map(many1(none_of("\r\n=|]")), |x| x.iter().collect::<String>());
-    let alias = map(many1(none_of("\r\n=]")), |x| x.iter().collect::<String>());
+fn rich_text_part_formatted<'a>(
+    input: Input<'a>,
+    sections: &Sections<'a>,
+) -> ParseResult<'a, GreenElement> {
+    alt((
+        |input| formatted_section(input, "***", sections),
+        |input| formatted_section(input, "**", sections),
+        |input| formatted_section(input, "*", sections),
+        |input| formatted_section(input, "__", sections),
+        |input| formatted_section(input, "_", sections),
+        |input| formatted_section(input, "~~", sections),
+    ))
+    .parse_complete(input)
+}
 
-    map(
-        (referent, opt(preceded(tag("|"), alias))),
-        |(referent, alias)| Reference { referent, alias },
+/// A section of rich text enclosed by `delimiter` on both sides, which may itself contain formatting.
+///
+/// Like in markdown, the section may neither start nor end with whitespace, so `2 * 3` stays as it is.
+fn formatted_section<'a>(
+    input: Input<'a>,
+    delimiter: &'static str,
+    sections: &Sections<'a>,
+) -> ParseResult<'a, GreenElement> {
+    let key = (input.location_offset(), delimiter);
+    if let Some(section) = sections.borrow().get(&key) {
+        return section.clone();
+    }
+
+    let opening = terminated(tag(delimiter), not(satisfy(char::is_whitespace)));
+    let content = verify(
+        node(
+            RichText,
+            many1(preceded(not(tag(delimiter)), |input| {
+                rich_text_part(input, sections)
+            })),
+        ),
+        |content: &GreenElement| !ends_with_whitespace(content),
+    );
+
+    let section = node(
+        Formatted,
+        (
+            token(Delimiter, opening),
+            content,
+            token(Delimiter, tag(delimiter)),
+        ),
     )
-    .parse(input)
+    .parse_complete(input);
+
+    sections.borrow_mut().insert(key, section.clone());
+    section
 }
 
-fn identifier(input: &str) -> IResult<&str, String> {
-    map(alphanumeric1, &str::to_string).parse(input)
+/// `[name]text[/name]`, as older scripts write formatting that has no markers of its own.
+fn rich_text_part_tagged<'a>(
+    input: Input<'a>,
+    sections: &Sections<'a>,
+) -> ParseResult<'a, GreenElement> {
+    let name = take_while1(|c: char| c.is_alphanumeric() || c == '-' || c == '_');
+    let (_, name) = preceded(tag("["), terminated(name, tag("]"))).parse_complete(input)?;
+    let closing = format!("[/{}]", name.fragment());
+
+    let content = node(
+        RichText,
+        many1(preceded(not(tag("[/")), |input| {
+            rich_text_part(input, sections)
+        })),
+    );
+
+    node(
+        Formatted,
+        (
+            token(Delimiter, (tag("["), tag(*name.fragment()), tag("]"))),
+            content,
+            token(Delimiter, context("closing tag", tag(closing.as_str()))),
+        ),
+    )
+    .parse_complete(input)
 }
 
-fn line(input: &str) -> IResult<&str, String> {
-    map(many_till(anychar, alt((line_ending, eof))), |x| {
-        x.0.into_iter().collect::<String>()
-    })
-    .parse(input)
+fn ends_with_whitespace(rich_text: &GreenElement) -> bool {
+    let GreenElement::Node(node) = rich_text else {
+        return false;
+    };
+
+    matches!(
+        node.children().last(),
+        Some(GreenElement::Token(token)) if token.kind() == Text && token.text().ends_with(char::is_whitespace)
+    )
+}
+
+/// A backslash followed by a character that would otherwise have a special meaning.
+fn rich_text_part_escaped(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    token(Escape, (tag("\\"), one_of(ESCAPABLE_CHARACTERS))).parse_complete(input)
+}
+
+fn rich_text_part_text(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    // Underscores between two word characters (as in `snake_case`) are plain text.
+    let intraword_underscore = recognize((
+        satisfy(char::is_alphanumeric),
+        tag("_"),
+        peek(satisfy(char::is_alphanumeric)),
+    ));
+    let parser = many1(alt((
+        intraword_underscore,
+        recognize(none_of("\r\n=[]*_~\\{")),
+    )));
+
+    token(Text, parser).parse_complete(input)
+}
+
+/// A formatting marker that doesn't open a section, or a brace that doesn't open a variable, is just text.
+fn rich_text_part_marker(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    token(Text, one_of("*_~\\{")).parse_complete(input)
+}
+
+const ESCAPABLE_CHARACTERS: &str = "\\*_~[]=@#{},->";
+
+fn reference(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    let referent = token(Referent, many1(none_of("\r\n=|]")));
+    let alias = token(Alias, many1(none_of("\r\n=]")));
+
+    node(Reference, (referent, opt((token(Pipe, tag("|")), alias)))).parse_complete(input)
+}
+
+fn identifier(input: Input<'_>) -> ParseResult<'_, GreenElement> {
+    token(Ident, alphanumeric1).parse_complete(input)
 }
@@ -0,0 +1,284 @@
+//! Parsing a file again after an edit, without parsing the scenes the edit didn't touch.
+//!
+//! Parsing only depends on what came before through the scene it's in, so parsing again can start
+//! at the last scene starting before the edit. Once it gets to a scene after the edit that starts
+//! where it did before, everything from there on would be parsed as it was, so it is kept.
+//!
+//! The scenes kept after the edit only moved, so their spans can be shifted once after many edits,
+//! with [`reparse_with`] and [`PendingShifts`].
+
+use super::*;
+use crate::{
+    diagnostic::LineIndex,
+    syntax::lower_scene,
+    types::{RichText, RichTextPart, SceneItem, Spanned},
+};
+
+/// A change to a source, which replaced the text in `range` with `inserted` bytes of new text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub range: Span,
+    pub inserted: usize,
+}
+
+/// Parses a file again after `edit`, giving the same as [`parse_tree_recovering`] and
+/// [`parse_recovering`] would, but only parsing again the scenes the edit touched.
+///
+/// `source` is the source after the edit, and `tree`, `file` and `errors` are what
+/// was parsed from it before the edit, which are updated to match it.
+pub fn reparse(
+    source: &str,
+    edit: &Edit,
+    tree: &mut Arc<GreenNode>,
+    file: &mut types::File,
+    errors: &mut Vec<RecoveredError>,
+) {
+    let mut shifts = PendingShifts::default();
+    reparse_with(
+        source,
+        &LineIndex::new(source),
+        edit,
+        tree,
+        file,
+        errors,
+        &mut shifts,
+    );
+    shifts.apply(file);
+}
+
+/// Like [`reparse`], with the line index of `source` kept by the caller,
+/// and leaving the spans of the scenes after the edit to be shifted by `shifts`.
+pub fn reparse_with(
+    source: &str,
+    lines: &LineIndex,
+    edit: &Edit,
+    tree: &mut Arc<GreenNode>,
+    file: &mut types::File,
+    errors: &mut Vec<RecoveredError>,
+    shifts: &mut PendingShifts,
+) {
+    if !reparse_scenes(source, lines, edit, tree, file, errors, shifts) {
+        (*tree, *errors) = parse_tree_recovering(source);
+        *file = lower(&SyntaxNode::new_root(tree.clone()));
+        shifts.0.clear();
+    }
+}
+
+/// How far each scene of a file has moved since its spans were last shifted.
+#[derive(Debug, Default)]
+pub struct PendingShifts(Vec<isize>);
+
+impl PendingShifts {
+    /// Shifts the spans of the scenes that moved, so they match the source again.
+    pub fn apply(&mut self, file: &mut types::File) {
+        for (scene, delta) in file.scenes.iter_mut().zip(self.0.drain(..)) {
+            if delta != 0 {
+                shift_scene(scene, delta);
+            }
+        }
+    }
+}
+
+/// Parses again the scenes touched by `edit`, unless the edit is somewhere
+/// that doesn't allow it, like before the first scene.
+fn reparse_scenes(
+    source: &str,
+    lines: &LineIndex,
+    edit: &Edit,
+    tree: &mut Arc<GreenNode>,
+    file: &mut types::File,
+    errors: &mut Vec<RecoveredError>,
+    shifts: &mut PendingShifts,
+) -> bool {
+    let old_len = tree.len();
+    let delta = edit.inserted as isize - edit.range.len() as isize;
+    if edit.range.end > old_len || source.len() as isize != old_len as isize + delta {
+        return false;
+    }
+
+    let root = SyntaxNode::new_root(tree.clone());
+    let scenes: Vec<SyntaxNode> = root
+        .children()
+        .filter(|child| child.kind() == Scene)
+        .collect();
+
+    // The scene before the edit may have been affected too, by whatever its last item
+    // looked ahead at, but never by more than the first character after it.
+    let Some(first) = scenes
+        .iter()
+        .rposition(|scene| scene.span().start < edit.range.start)
+    else {
+        return false;
+    };
+    let start = scenes[first].span().start;
+
+    // The scene has to start over as it did, rather than be added to the one before it.
+    let input = Input::new(source);
+    let rest = input.take_from(start);
+    let starts_scene = scene(rest).is_ok()
+        || (scene_item(rest).is_err() && unterminated_scene_header(rest).is_ok());
+    if !starts_scene {
+        return false;
+    }
+
+    // Where the scenes after the edit start now.
+    let shifted: Vec<(usize, usize)> = scenes
+        .iter()
+        .enumerate()
+        .skip(first + 1)
+        .filter(|(_, scene)| scene.span().start >= edit.range.end)
+        .map(|(idx, scene)| (idx, shift(scene.span().start, delta)))
+        .collect();
+
+    let mut recovery = Recovery::new(source);
+    let stopped = recovery.run(rest, |offset| {
+        shifted.iter().any(|&(_, start)| start == offset)
+    });
+    let (children, new_errors) = recovery.finish();
+    let parsed = children
+        .iter()
+        .filter(|child| child.kind() == Scene)
+        .count();
+
+    // The scenes kept from before, which start where parsing stopped.
+    let kept = shifted
+        .iter()
+        .find(|&&(_, start)| start == stopped.location_offset())
+        .map(|&(idx, _)| idx);
+    let (kept_children, kept_scenes, kept_from) = match kept {
+        Some(idx) => (scenes[idx].index(), idx, scenes[idx].span().start),
+        None => (root.green().children().len(), scenes.len(), old_len),
+    };
+
+    *tree = Arc::new(
+        root.green()
+            .splice_children(scenes[first].index()..kept_children, children),
+    );
+
+    // Errors are kept in source order, from before the scenes parsed again and after them.
+    let (before, after): (Vec<_>, Vec<_>) = std::mem::take(errors)
+        .into_iter()
+        .partition(|error| error.skipped.start < start);
+    let after = after
+        .into_iter()
+        .filter(|error| kept.is_some() && error.skipped.start >= kept_from)
+        .map(|mut error| {
+            shift_span(&mut error.skipped, delta);
+            shift_span(&mut error.diagnostic.span, delta);
+            error.diagnostic.start = lines.line_column(error.diagnostic.span.start);
+            error.diagnostic.end = lines.line_column(error.diagnostic.span.end);
+            error
+        });
+    *errors = before.into_iter().chain(new_errors).chain(after).collect();
+
+    let root = SyntaxNode::new_root(tree.clone());
+    let new_scenes: Vec<_> = root
+        .children()
+        .filter(|child| child.kind() == Scene)
+        .skip(first)
+        .take(parsed)
+        .map(|node| Spanned::new(lower_scene(&node), node.span()))
+        .collect();
+
+    // The scenes parsed again are where they are now, and the ones kept moved by the edit.
+    shifts.0.resize(file.scenes.len(), 0);
+    file.scenes.splice(first..kept_scenes, new_scenes);
+    shifts
+        .0
+        .splice(first..kept_scenes, std::iter::repeat_n(0, parsed));
+    for shift in &mut shifts.0[first + parsed..] {
+        *shift += delta;
+    }
+
+    true
+}
+
+fn shift(offset: usize, delta: isize) -> usize {
+    offset.wrapping_add_signed(delta)
+}
+
+fn shift_span(span: &mut Span, delta: isize) {
+    *span = shift(span.start, delta)..shift(span.end, delta);
+}
+
+fn shift_scene(scene: &mut Spanned<types::Scene>, delta: isize) {
+    shift_span(&mut scene.span, delta);
+
+    if let Some(name) = &mut scene.name {
+        shift_span(&mut name.span, delta);
+    }
+
+    scene.meta = std::mem::take(&mut scene.meta)
+        .into_iter()
+        .map(|(mut key, mut value)| {
+            shift_span(&mut key.span, delta);
+            shift_span(&mut value.span, delta);
+            shift_value(&mut value, delta);
+            (key, value)
+        })
+        .collect();
+
+    for item in &mut scene.items {
+        shift_item(item, delta);
+    }
+}
+
+fn shift_item(item: &mut Spanned<SceneItem>, delta: isize) {
+    shift_span(&mut item.span, delta);
+
+    match &mut item.node {
+        SceneItem::ActionBlock(rich_text) | SceneItem::SpoilerBlock(rich_text) => {
+            shift_rich_text(rich_text, delta)
+        }
+        SceneItem::DialogueBlock { speaker, block } => {
+            shift_span(&mut speaker.span, delta);
+            for rich_text in block {
+                shift_rich_text(rich_text, delta);
+            }
+        }
+        SceneItem::TaggedAction(tag, rich_text) => {
+            shift_span(&mut tag.span, delta);
+            shift_rich_text(rich_text, delta);
+        }
+        SceneItem::Cont(target) => shift_span(&mut target.span, delta),
+        SceneItem::If(condition, items) => {
+            shift_rich_text(condition, delta);
+            for item in items {
+                shift_item(item, delta);
+            }
+        }
+        SceneItem::Set(name, value) => {
+            shift_span(&mut name.span, delta);
+            if let Some(value) = value {
+                shift_span(&mut value.span, delta);
+                shift_value(value, delta);
+            }
+        }
+    }
+}
+
+fn shift_value(value: &mut types::Value, delta: isize) {
+    match value {
+        types::Value::RichText(rich_text) => shift_rich_text(rich_text, delta),
+        types::Value::List(values) => {
+            for value in values {
+                shift_span(&mut value.span, delta);
+                shift_value(value, delta);
+            }
+        }
+        types::Value::Reference(reference) => shift_span(&mut reference.span, delta),
+        _ => {}
+    }
+}
+
+fn shift_rich_text(rich_text: &mut RichText, delta: isize) {
+    for part in &mut rich_text.0 {
+        shift_span(&mut part.span, delta);
+
+        match &mut part.node {
+            RichTextPart::Text(_) | RichTextPart::Variable(_) => {}
+            RichTextPart::Reference(reference) => shift_span(&mut reference.span, delta),
+            RichTextPart::FormattedSection(_, content) => shift_rich_text(content, delta),
+        }
+    }
+}
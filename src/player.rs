@@ -1,77 +1,913 @@
-use crate::types::{File, RichText, Scene, SceneItem, Value};
+//! Playing a script in the terminal, as the game it describes.
+//!
+//! Scenes are played one after another, unless an `@CONT` continues somewhere else.
+//! `@IF`s whose condition is something an `@SET` sets happen if it is set,
+//! and the others are choices the player makes.
 
-pub fn play(file: File) {
-    if let Some(frontmatter) = file.frontmatter {
-        if let Some(title) = frontmatter.get("T") {
-            play_value(title);
+use crate::types::{File, Reference, RichText, RichTextPart, Scene, SceneItem, Spanned, Value};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+/// Plays a file from where `session` is at, saving the session to `save_to` as each scene starts.
+///
+/// Fails if the session is at a scene the file doesn't have.
+pub fn play(file: &File, session: Session, save_to: Option<&Path>) -> Result<(), String> {
+    let mut scene = match &session.scene {
+        Some(name) => session.scene_in(file).ok_or_else(|| {
+            format!("the session is at `{name}`, but there is no scene called that")
+        })?,
+        None => 0,
+    };
+    let mut player = Player::new(file, session.state);
+
+    if session.scene.is_none()
+        && let Some(frontmatter) = &file.frontmatter
+    {
+        for key in ["Title", "Subtitle"] {
+            if let Some(value) = frontmatter.get(key) {
+                player.play_value(value);
+            }
+        }
+    }
+
+    while let Some(current) = file.scenes.get(scene) {
+        if let Some(path) = save_to {
+            let session = Session {
+                scene: Some(saved_scene(current, scene)),
+                state: player.state.clone(),
+            };
+            if let Err(error) = session.save(path) {
+                eprintln!("error: couldn't save the session: {error}");
+            }
         }
 
-        if let Some(subtitle) = frontmatter.get("S") {
-            play_value(subtitle);
+        scene = match player.play_items(&current.items) {
+            Flow::Next => scene + 1,
+            Flow::Jump(to) => to,
+            Flow::End => return Ok(()),
+        };
+    }
+
+    Ok(())
+}
+
+/// Where a playthrough is at, which can be saved to pick it back up later.
+///
+/// It is saved as the scene it's at and the variables set so far, like:
+///
+/// ```text
+/// @SCENE Apple
+/// @PRONOUNS she/her/her/hers/herself
+/// @SET Has Apple = true
+/// @SET User = Claire
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    /// The name of the scene being played, or `#` and its position if it has none,
+    /// which is nothing before the first scene starts.
+    ///
+    /// Scenes are saved by name so that sessions still work after scenes are added or moved.
+    /// Names that start with `#` or `\` are saved with a `\` before them, so they're never
+    /// taken for a position.
+    pub scene: Option<String>,
+    pub state: State,
+}
+
+impl Session {
+    /// The position in [`File::scenes`] of the scene the session is at, if the file has it.
+    pub fn scene_in(&self, file: &File) -> Option<usize> {
+        let saved = self.scene.as_deref()?;
+
+        if let Some(position) = saved.strip_prefix('#') {
+            let idx = position.parse::<usize>().ok()?.checked_sub(1)?;
+            return file
+                .scenes
+                .get(idx)
+                .is_some_and(|scene| scene.name.is_none())
+                .then_some(idx);
         }
+
+        let name = saved.strip_prefix('\\').unwrap_or(saved);
+        file.scenes
+            .iter()
+            .position(|scene| scene.name.as_ref().is_some_and(|scene| scene.node == name))
     }
 
-    let mut scene_index = 0;
-    let mut current_scene = file.scenes.first();
+    pub fn load(path: &Path) -> std::io::Result<Session> {
+        let text = std::fs::read_to_string(path)?;
+        text.parse()
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
 
-    while let Some(scene) = &current_scene {
-        play_scene(scene);
-        scene_index += 1;
-        current_scene = file.scenes.get(scene_index);
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
     }
 }
 
-pub fn wait() {
-    use std::io::Read;
-    let _ = std::io::stdin().read(&mut [0u8]).unwrap();
+/// How a scene is saved in a session, which [`Session::scene_in`] finds it by.
+fn saved_scene(scene: &Scene, idx: usize) -> String {
+    match &scene.name {
+        Some(name) if name.starts_with(['#', '\\']) => format!("\\{}", name.node),
+        Some(name) => name.node.clone(),
+        None => format!("#{}", idx + 1),
+    }
 }
 
-pub fn play_scene(scene: &Scene) {
-    for item in scene.items.iter() {
-        play_item(item);
-        wait();
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(scene) = &self.scene {
+            writeln!(f, "@SCENE {scene}")?;
+        }
+        if let Some(pronouns) = &self.state.pronouns {
+            writeln!(f, "@PRONOUNS {pronouns}")?;
+        }
+        for (name, value) in self.state.variables.iter() {
+            writeln!(f, "@SET {name} = {value}")?;
+        }
+        Ok(())
     }
 }
 
-pub fn play_item(item: &SceneItem) {
-    match item {
-        SceneItem::ActionBlock(rich_text) => play_rich_text(rich_text),
-        SceneItem::DialogueBlock { speaker, block } => {
-            play_text(speaker.alias.as_ref().unwrap_or(&speaker.referent));
-            for line in block.iter() {
-                play_rich_text(line);
+impl std::str::FromStr for Session {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut session = Session::default();
+
+        for (idx, line) in text.lines().enumerate() {
+            let invalid = || format!("line {} of the session is invalid: `{line}`", idx + 1);
+
+            match line.trim().split_once(' ') {
+                Some(("@SCENE", scene)) => session.scene = Some(scene.trim().to_owned()),
+                Some(("@PRONOUNS", pronouns)) => {
+                    session.state.pronouns = Some(Pronouns::parse(pronouns).ok_or_else(invalid)?);
+                }
+                Some(("@SET", variable)) => {
+                    let (name, value) = variable.split_once('=').ok_or_else(invalid)?;
+                    session
+                        .state
+                        .variables
+                        .insert(name.trim().to_owned(), value.trim().to_owned());
+                }
+                _ if line.trim().is_empty() => {}
+                _ => return Err(invalid()),
             }
         }
-        SceneItem::SpoilerBlock(_) => {}
-        SceneItem::TaggedAction(_, rich_text) => play_rich_text(rich_text),
-        SceneItem::Cont(_) => todo!(),
-        SceneItem::If(_, _) => todo!(),
+
+        Ok(session)
     }
 }
 
-pub fn play_value(value: &Value) {
-    match value {
-        Value::RichText(rich_text) => play_rich_text(rich_text),
-        Value::List(values) => {
-            println!("[");
-            for value in values {
-                play_value(value);
+/// What is known while playing a file, like the values of variables.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    /// The values of variables, by name. Flags raised with `@SET` are `true`.
+    pub variables: BTreeMap<String, String>,
+    /// The pronouns of the player, once they've said what they are.
+    pub pronouns: Option<Pronouns>,
+}
+
+impl State {
+    /// Writes out some rich text, with its variables replaced by their values.
+    pub fn render(&mut self, rich_text: &RichText) -> String {
+        let mut text = String::new();
+
+        for part in rich_text.0.iter() {
+            match &part.node {
+                RichTextPart::Text(part) => text.push_str(part),
+                RichTextPart::Reference(reference) => text.push_str(&reference.referent),
+                RichTextPart::FormattedSection(_, rich_text) => {
+                    text.push_str(&self.render(rich_text))
+                }
+                RichTextPart::Variable(name) => text.push_str(&self.resolve(name)),
             }
-            println!("]");
         }
+
+        text
     }
+
+    /// The value of a variable, or the pronoun it stands for.
+    ///
+    /// The player is asked for whatever isn't known yet.
+    pub fn resolve(&mut self, name: &str) -> String {
+        if Pronouns::is_placeholder(name) {
+            let pronouns = self.pronouns.get_or_insert_with(|| {
+                Pronouns::parse_or_they(&ask(
+                    "What are your pronouns? (she/her, he/him, they/them)",
+                ))
+            });
+            return pronouns.get(name).unwrap();
+        }
+
+        self.variables
+            .entry(name.to_owned())
+            .or_insert_with(|| match ask(&format!("What is {name}?")) {
+                answer if answer.is_empty() => name.to_owned(),
+                answer => answer,
+            })
+            .clone()
+    }
+
+    /// Whether a flag is raised, or a variable is set to something other than `false` or `0`.
+    pub fn is_set(&self, name: &str) -> bool {
+        self.variables
+            .get(name)
+            .is_some_and(|value| !matches!(value.as_str(), "" | "false" | "0"))
+    }
+}
+
+/// A set of pronouns, like they/them/their/theirs/themself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pronouns {
+    /// Like "they", in "they are here".
+    pub subject: String,
+    /// Like "them", in "talk to them".
+    pub object: String,
+    /// Like "their", in "their bag".
+    pub possessive: String,
+    /// Like "theirs", in "the bag is theirs".
+    pub possessive_pronoun: String,
+    /// Like "themself", in "they did it themself".
+    pub reflexive: String,
 }
 
-pub fn play_rich_text(rich_text: &RichText) {
-    for part in rich_text.0.iter() {
-        match part {
-            crate::types::RichTextPart::Text(text) => play_text(text),
-            crate::types::RichTextPart::Reference(reference) => play_text(&reference.referent),
-            crate::types::RichTextPart::FormattedSection(_, rich_text) => play_rich_text(rich_text),
+impl Pronouns {
+    pub fn new(pronouns: [&str; 5]) -> Self {
+        let [subject, object, possessive, possessive_pronoun, reflexive] =
+            pronouns.map(str::to_owned);
+        Pronouns {
+            subject,
+            object,
+            possessive,
+            possessive_pronoun,
+            reflexive,
         }
     }
+
+    pub fn they() -> Self {
+        Pronouns::new(["they", "them", "their", "theirs", "themself"])
+    }
+
+    pub fn she() -> Self {
+        Pronouns::new(["she", "her", "her", "hers", "herself"])
+    }
+
+    pub fn he() -> Self {
+        Pronouns::new(["he", "him", "his", "his", "himself"])
+    }
+
+    /// Reads pronouns written like `she/her` or `she`, or as all five of them,
+    /// like `xe/xem/xyr/xyrs/xemself`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().to_lowercase();
+        let parts: Vec<&str> = text.split('/').map(str::trim).collect();
+
+        if let Ok(parts) = <[&str; 5]>::try_from(parts.as_slice()) {
+            return Some(Pronouns::new(parts));
+        }
+
+        match parts.first().copied() {
+            Some("they") => Some(Pronouns::they()),
+            Some("she") => Some(Pronouns::she()),
+            Some("he") => Some(Pronouns::he()),
+            _ => None,
+        }
+    }
+
+    /// Reads pronouns like [`Pronouns::parse`], going with they/them if they can't be read.
+    pub fn parse_or_they(text: &str) -> Self {
+        Pronouns::parse(text).unwrap_or_else(Pronouns::they)
+    }
+
+    /// Whether a variable like `{they}` or `{Their}` stands for a pronoun.
+    pub fn is_placeholder(name: &str) -> bool {
+        Pronouns::they().get(name).is_some()
+    }
+
+    /// The pronoun a placeholder stands for, capitalized if the placeholder is.
+    pub fn get(&self, placeholder: &str) -> Option<String> {
+        let pronoun = match placeholder.to_lowercase().as_str() {
+            "they" => &self.subject,
+            "them" => &self.object,
+            "their" => &self.possessive,
+            "theirs" => &self.possessive_pronoun,
+            "themself" | "themselves" => &self.reflexive,
+            _ => return None,
+        };
+
+        match placeholder.starts_with(char::is_uppercase) {
+            true => Some(capitalize(pronoun)),
+            false => Some(pronoun.clone()),
+        }
+    }
+}
+
+/// Writes all five pronouns, like `xe/xem/xyr/xyrs/xemself`.
+impl std::fmt::Display for Pronouns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}/{}/{}",
+            self.subject, self.object, self.possessive, self.possessive_pronoun, self.reflexive
+        )
+    }
+}
+
+/// What to do after playing some items.
+#[derive(Debug, PartialEq)]
+enum Flow {
+    /// Go on to the next scene.
+    Next,
+    /// Continue to the scene at this position.
+    Jump(usize),
+    /// Stop playing.
+    End,
+}
+
+/// Something the player can choose, out of the `@IF`s and `@CONT`s written one after another.
+enum Choice<'a> {
+    If(&'a RichText, &'a [Spanned<SceneItem>]),
+    /// An `@CONT`, with the comment written right before it, which describes it.
+    Cont(&'a Spanned<Reference>, Option<&'a RichText>),
+}
+
+struct Player<'a> {
+    file: &'a File,
+    state: State,
+    /// Every flag and variable the file sets, which `@IF`s check rather than ask about.
+    settable: HashSet<&'a str>,
 }
 
-pub fn play_text(text: &str) {
+impl<'a> Player<'a> {
+    fn new(file: &'a File, state: State) -> Self {
+        let settable = file
+            .scenes
+            .iter()
+            .flat_map(|scene| scene.all_items())
+            .filter_map(|item| match &item.node {
+                SceneItem::Set(name, _) => Some(name.node.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        Player {
+            file,
+            state,
+            settable,
+        }
+    }
+
+    fn play_items(&mut self, items: &'a [Spanned<SceneItem>]) -> Flow {
+        let mut idx = 0;
+
+        while let Some(item) = items.get(idx) {
+            idx += 1;
+
+            let flow = match &item.node {
+                SceneItem::If(condition, children) if self.is_checked(condition) => {
+                    match self.state.is_set(condition.as_string().trim()) {
+                        true => self.play_items(children),
+                        false => Flow::Next,
+                    }
+                }
+                SceneItem::If(..) | SceneItem::Cont(_) => {
+                    let (choices, end) = self.choices(items, idx - 1);
+                    idx = end;
+                    self.choose(choices)
+                }
+                SceneItem::Set(name, value) => {
+                    let value = value
+                        .as_ref()
+                        .map_or("true".to_owned(), |value| self.render_value(value));
+                    self.state.variables.insert(name.node.clone(), value);
+                    Flow::Next
+                }
+                SceneItem::SpoilerBlock(_) => Flow::Next,
+                _ => {
+                    self.play_item(item);
+                    self.pause()
+                }
+            };
+
+            if flow != Flow::Next {
+                return flow;
+            }
+        }
+
+        Flow::Next
+    }
+
+    /// Whether an `@IF` checks a flag or variable, rather than being up to the player.
+    fn is_checked(&self, condition: &RichText) -> bool {
+        self.settable.contains(condition.as_string().trim())
+    }
+
+    /// The choices written one after another from `start` in `items`,
+    /// along with where the items after them start.
+    ///
+    /// `@IF`s and `@CONT`s are never choices together, as an `@IF` being chosen
+    /// doesn't mean the scene isn't meant to continue. Comments between `@CONT`s
+    /// are skipped, as they describe the `@CONT` after them.
+    fn choices(&self, items: &'a [Spanned<SceneItem>], start: usize) -> (Vec<Choice<'a>>, usize) {
+        let mut choices = vec![];
+        let mut comment = match start.checked_sub(1).map(|before| &items[before].node) {
+            Some(SceneItem::SpoilerBlock(text)) => Some(text),
+            _ => None,
+        };
+        let conts = matches!(items[start].node, SceneItem::Cont(_));
+        let mut idx = start;
+
+        while let Some(item) = items.get(idx) {
+            match &item.node {
+                SceneItem::If(condition, children) if !conts && !self.is_checked(condition) => {
+                    choices.push(Choice::If(condition, children));
+                }
+                SceneItem::Cont(target) if conts => choices.push(Choice::Cont(target, comment)),
+                SceneItem::SpoilerBlock(text) if conts => {
+                    comment = Some(text);
+                    idx += 1;
+                    continue;
+                }
+                _ => break,
+            }
+
+            comment = None;
+            idx += 1;
+        }
+
+        // A comment after the choices isn't part of them.
+        while idx > start && matches!(items[idx - 1].node, SceneItem::SpoilerBlock(_)) {
+            idx -= 1;
+        }
+
+        (choices, idx)
+    }
+
+    /// Lets the player make one of `choices`, and plays it.
+    ///
+    /// A lone `@CONT` isn't a choice, so it's followed right away.
+    /// Out of `@IF`s, the player may also choose none of them.
+    fn choose(&mut self, choices: Vec<Choice<'a>>) -> Flow {
+        if let [Choice::Cont(target, _)] = choices.as_slice() {
+            return self.jump(target);
+        }
+
+        let optional = choices
+            .iter()
+            .all(|choice| matches!(choice, Choice::If(..)));
+
+        for (idx, choice) in choices.iter().enumerate() {
+            let label = match choice {
+                Choice::If(condition, _) => self.state.render(condition),
+                Choice::Cont(_, Some(comment)) => self.state.render(comment),
+                Choice::Cont(target, None) => target
+                    .alias
+                    .clone()
+                    .unwrap_or_else(|| target.referent.clone()),
+            };
+            play_text(&format!("  {}. {}", idx + 1, label.trim()));
+        }
+
+        let question = match optional {
+            true => format!("Choose 1-{}, or nothing to go on:", choices.len()),
+            false => format!("Choose 1-{}:", choices.len()),
+        };
+
+        loop {
+            let Some(answer) = read_answer(&question) else {
+                return Flow::End;
+            };
+
+            if answer.is_empty() && optional {
+                return Flow::Next;
+            }
+            if is_quit(&answer) {
+                return Flow::End;
+            }
+
+            let choice = answer
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| choices.get(number.checked_sub(1)?));
+            match choice {
+                Some(Choice::If(_, children)) => return self.play_items(children),
+                Some(Choice::Cont(target, _)) => return self.jump(target),
+                None => continue,
+            }
+        }
+    }
+
+    /// Continues to the scene an `@CONT` is to.
+    fn jump(&self, target: &Reference) -> Flow {
+        let scene = self.file.scenes.iter().position(|scene| {
+            scene
+                .name
+                .as_ref()
+                .is_some_and(|name| name.node == target.referent)
+        });
+
+        match scene {
+            Some(scene) => Flow::Jump(scene),
+            None => {
+                eprintln!("error: there is no scene called `{}`", target.referent);
+                Flow::End
+            }
+        }
+    }
+
+    /// Waits for the player to read what was played, and to press Enter to go on.
+    fn pause(&self) -> Flow {
+        match read_answer("") {
+            Some(answer) if !is_quit(&answer) => Flow::Next,
+            _ => Flow::End,
+        }
+    }
+
+    fn play_item(&mut self, item: &SceneItem) {
+        match item {
+            SceneItem::ActionBlock(rich_text) => self.play_rich_text(rich_text),
+            SceneItem::DialogueBlock { speaker, block } => {
+                play_text(speaker.alias.as_ref().unwrap_or(&speaker.referent));
+                for line in block.iter() {
+                    self.play_rich_text(line);
+                }
+            }
+            SceneItem::TaggedAction(_, rich_text) => self.play_rich_text(rich_text),
+            SceneItem::SpoilerBlock(_)
+            | SceneItem::Cont(_)
+            | SceneItem::If(..)
+            | SceneItem::Set(..) => {}
+        }
+    }
+
+    fn play_value(&mut self, value: &Value) {
+        play_text(&self.render_value(value));
+    }
+
+    fn render_value(&mut self, value: &Value) -> String {
+        match value {
+            Value::RichText(rich_text) => self.state.render(rich_text),
+            Value::List(values) => values
+                .iter()
+                .map(|value| self.render_value(value))
+                .collect::<Vec<_>>()
+                .join(", "),
+            value => value.as_string(),
+        }
+    }
+
+    fn play_rich_text(&mut self, rich_text: &RichText) {
+        let text = self.state.render(rich_text);
+        play_text(&text);
+    }
+}
+
+fn play_text(text: &str) {
     println!("{}", text);
 }
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Whether the player answered that they want to stop playing.
+fn is_quit(answer: &str) -> bool {
+    matches!(answer, "q" | "quit")
+}
+
+/// Asks the player something, giving back their answer.
+fn ask(question: &str) -> String {
+    read_answer(question).unwrap_or_default()
+}
+
+/// Asks the player something, giving back their answer, or nothing if there's nothing left to read.
+fn read_answer(question: &str) -> Option<String> {
+    if !question.is_empty() {
+        print!("{question} ");
+        let _ = std::io::stdout().flush();
+    }
+
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rich text of the only action in `body`.
+    fn rich_text(body: &str) -> RichText {
+        let file = crate::parser::parse(&format!("TITLE\n\n====\n\n{body}\n")).unwrap();
+        match &file.scenes[0].items[0].node {
+            SceneItem::ActionBlock(rich_text) => rich_text.clone(),
+            item => panic!("not an action: {item:?}"),
+        }
+    }
+
+    #[test]
+    fn pronouns_are_capitalized_like_their_placeholder() {
+        let she = Pronouns::she();
+
+        assert_eq!(she.get("their").as_deref(), Some("her"));
+        assert_eq!(she.get("Their").as_deref(), Some("Her"));
+        assert_eq!(she.get("They").as_deref(), Some("She"));
+        assert_eq!(she.get("THEM").as_deref(), Some("Her"));
+        assert_eq!(she.get("Claire"), None);
+    }
+
+    #[test]
+    fn themselves_is_the_same_as_themself() {
+        let he = Pronouns::he();
+
+        assert_eq!(he.get("themselves").as_deref(), Some("himself"));
+        assert_eq!(he.get("Themselves").as_deref(), Some("Himself"));
+        assert_eq!(
+            Pronouns::they().get("themselves").as_deref(),
+            Some("themself")
+        );
+    }
+
+    #[test]
+    fn all_five_pronouns_can_be_given() {
+        let xe = Pronouns::parse("Xe / xem / xyr / xyrs / xemself").unwrap();
+
+        assert_eq!(xe, Pronouns::new(["xe", "xem", "xyr", "xyrs", "xemself"]));
+        assert_eq!(xe.to_string(), "xe/xem/xyr/xyrs/xemself");
+        assert_eq!(Pronouns::parse(&xe.to_string()), Some(xe));
+    }
+
+    #[test]
+    fn pronouns_that_cant_be_read_are_they_them() {
+        assert_eq!(Pronouns::parse_or_they(""), Pronouns::they());
+        assert_eq!(Pronouns::parse_or_they("xe/xem"), Pronouns::they());
+        assert_eq!(Pronouns::parse_or_they("she/her"), Pronouns::she());
+    }
+
+    #[test]
+    fn placeholders_are_replaced_by_pronouns() {
+        let mut state = State {
+            pronouns: Some(Pronouns::she()),
+            ..State::default()
+        };
+        let text = rich_text("{They} found {themselves} in {their} room, and it was {theirs}.");
+
+        assert_eq!(
+            state.render(&text),
+            "She found herself in her room, and it was hers."
+        );
+    }
+
+    #[test]
+    fn variables_are_replaced_by_their_values() {
+        let mut state = State::default();
+        state
+            .variables
+            .insert("User".to_owned(), "Claire".to_owned());
+
+        assert_eq!(
+            state.render(&rich_text("{User} wakes up.")),
+            "Claire wakes up."
+        );
+    }
+
+    #[test]
+    fn sessions_are_read_back_as_they_were_saved() {
+        let mut session = Session {
+            scene: Some("Key Scene".to_owned()),
+            state: State {
+                pronouns: Some(Pronouns::new(["xe", "xem", "xyr", "xyrs", "xemself"])),
+                ..State::default()
+            },
+        };
+        for (name, value) in [("Has Apple", "true"), ("Motto", "a = b, c = d")] {
+            session
+                .state
+                .variables
+                .insert(name.to_owned(), value.to_owned());
+        }
+
+        let saved = session.to_string();
+        assert_eq!(
+            saved,
+            "\
+@SCENE Key Scene
+@PRONOUNS xe/xem/xyr/xyrs/xemself
+@SET Has Apple = true
+@SET Motto = a = b, c = d
+"
+        );
+        assert_eq!(saved.parse::<Session>(), Ok(session));
+        assert_eq!("".parse::<Session>(), Ok(Session::default()));
+    }
+
+    #[test]
+    fn invalid_sessions_say_where_they_are_invalid() {
+        assert_eq!(
+            "@SCENE Start\n@SET Apples\n".parse::<Session>(),
+            Err("line 2 of the session is invalid: `@SET Apples`".to_owned())
+        );
+        assert!("@PRONOUNS it/its\n".parse::<Session>().is_err());
+        assert!("@GOTO Start\n".parse::<Session>().is_err());
+    }
+
+    #[test]
+    fn sessions_are_at_scenes_by_name() {
+        let file = crate::parser::parse("T\n\n== Start ==\n\nHi.\n\n====\n\nBye.\n").unwrap();
+        let at = |scene: &str| Session {
+            scene: Some(scene.to_owned()),
+            ..Session::default()
+        };
+
+        assert_eq!(at("Start").scene_in(&file), Some(0));
+        assert_eq!(at("#2").scene_in(&file), Some(1));
+        assert_eq!(at("Nowhere").scene_in(&file), None);
+        assert_eq!(Session::default().scene_in(&file), None);
+        assert!(play(&file, at("Nowhere"), None).is_err());
+    }
+
+    #[test]
+    fn unnamed_scenes_are_never_taken_for_named_ones() {
+        let source = "\
+T
+
+== Start ==
+
+Hi.
+
+====
+
+On.
+
+== Scene 2 ==
+
+On.
+
+== #2 ==
+
+Bye.
+";
+        let file = crate::parser::parse(source).unwrap();
+
+        let saved: Vec<_> = file
+            .scenes
+            .iter()
+            .enumerate()
+            .map(|(idx, scene)| saved_scene(scene, idx))
+            .collect();
+        assert_eq!(saved, ["Start", "#2", "Scene 2", "\\#2"]);
+
+        for (idx, scene) in saved.into_iter().enumerate() {
+            let session = Session {
+                scene: Some(scene),
+                ..Session::default()
+            };
+            let session: Session = session.to_string().parse().unwrap();
+            assert_eq!(session.scene_in(&file), Some(idx));
+        }
+
+        let at_third = Session {
+            scene: Some("#3".to_owned()),
+            ..Session::default()
+        };
+        assert_eq!(at_third.scene_in(&file), None);
+    }
+
+    #[test]
+    fn pronouns_are_read_from_how_they_are_written() {
+        for (text, pronouns) in [
+            ("she/her", Some(Pronouns::she())),
+            ("He", Some(Pronouns::he())),
+            (" they / them ", Some(Pronouns::they())),
+            ("she/her/her/hers/herself", Some(Pronouns::she())),
+            ("it/its", None),
+            ("", None),
+        ] {
+            assert_eq!(Pronouns::parse(text), pronouns, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn flags_and_variables_are_set_unless_false() {
+        let mut state = State::default();
+        let variables = [
+            ("Flag", "true"),
+            ("Name", "Claire"),
+            ("Off", "false"),
+            ("None", "0"),
+            ("Empty", ""),
+        ];
+        for (name, value) in variables {
+            state.variables.insert(name.to_owned(), value.to_owned());
+        }
+
+        assert!(state.is_set("Flag"));
+        assert!(state.is_set("Name"));
+        assert!(!state.is_set("Off"));
+        assert!(!state.is_set("None"));
+        assert!(!state.is_set("Empty"));
+        assert!(!state.is_set("Unknown"));
+    }
+
+    const CHOICES: &str = "\
+T
+
+== Hall ==
+
+Where to?
+
+// The door on the left.
+@CONT Left
+
+@CONT Right
+
+// Not about anything after it.
+
+@IF the player waves {
+    Hi.
+}
+
+@IF the player waits {
+    Nothing happens.
+}
+
+@IF Has Key {
+    The key glows.
+}
+
+@IF the player sits @CONT Left
+
+@CONT Right
+
+== Left ==
+
+@SET Has Key
+
+== Right ==
+
+Bye.
+";
+
+    /// The choices starting at the item `first` is the start of, along with the item after them.
+    fn choices_at(file: &File, first: &str) -> (Vec<String>, usize) {
+        let player = Player::new(file, State::default());
+        let (choices, end) = player.choices(&file.scenes[0].items, position(file, first));
+        let choices = choices
+            .iter()
+            .map(|choice| match choice {
+                Choice::If(condition, _) => format!("if {}", condition.as_string()),
+                Choice::Cont(target, None) => format!("cont {}", target.referent),
+                Choice::Cont(target, Some(comment)) => {
+                    format!("cont {}, {}", target.referent, comment.as_string().trim())
+                }
+            })
+            .collect();
+        (choices, end)
+    }
+
+    /// The position of the item `item` is the start of.
+    fn position(file: &File, item: &str) -> usize {
+        let start = CHOICES.find(item).unwrap();
+        file.scenes[0]
+            .items
+            .iter()
+            .position(|item| item.span.start == start)
+            .unwrap()
+    }
+
+    #[test]
+    fn conts_are_chosen_together_and_described_by_comments() {
+        let file = crate::parser::parse(CHOICES).unwrap();
+
+        let (choices, end) = choices_at(&file, "@CONT Left");
+        assert_eq!(choices, ["cont Left, The door on the left.", "cont Right"]);
+        assert_eq!(end, position(&file, "// Not about"));
+    }
+
+    #[test]
+    fn ifs_are_chosen_together_unless_they_are_checked() {
+        let file = crate::parser::parse(CHOICES).unwrap();
+
+        let (choices, end) = choices_at(&file, "@IF the player waves");
+        assert_eq!(choices, ["if the player waves", "if the player waits"]);
+        assert_eq!(end, position(&file, "@IF Has Key"));
+    }
+
+    #[test]
+    fn ifs_and_conts_are_not_chosen_together() {
+        let file = crate::parser::parse(CHOICES).unwrap();
+
+        let (choices, end) = choices_at(&file, "@IF the player sits");
+        assert_eq!(choices, ["if the player sits"]);
+        assert_eq!(end, position(&file, "@CONT Right\n\n== Left"));
+    }
+}
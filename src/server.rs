@@ -1,72 +1,559 @@
+use crate::diagnostic::{LineColumn, LineIndex, Severity};
+use crate::graph::Graph;
+use crate::syntax::{SyntaxKind, SyntaxNode};
+use crate::types::{RichText, Scene, SceneItem, Span, Spanned};
+use std::sync::{Arc, RwLock};
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
+use workspace::{Document, Workspace};
+
+mod code_actions;
+mod completion;
+mod hover;
+mod lookup;
+mod navigation;
+mod semantic_tokens;
+pub mod workspace;
 
 pub struct LanguageBackend {
     pub client: Client,
+    pub workspace: Arc<RwLock<Workspace>>,
+}
+
+impl LanguageBackend {
+    pub fn new(client: Client) -> Self {
+        LanguageBackend {
+            client,
+            workspace: Arc::default(),
+        }
+    }
+
+    /// Runs `f` on an open document, if there is one at `uri`.
+    fn with_document<T>(&self, uri: &Url, f: impl FnOnce(&Document) -> T) -> Option<T> {
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+        workspace.get(uri).map(f)
+    }
+
+    /// Sends the editor the syntax errors in a document, and the problems with its flow.
+    async fn publish_diagnostics(&self, uri: Url) {
+        let Some((diagnostics, version)) = self.with_document(&uri, |document| {
+            (document_diagnostics(document), document.version)
+        }) else {
+            return;
+        };
+
+        self.client
+            .publish_diagnostics(uri, diagnostics, Some(version))
+            .await;
+    }
+
+    fn workspace_mut(&self) -> std::sync::RwLockWriteGuard<'_, Workspace> {
+        self.workspace.write().expect("workspace lock was poisoned")
+    }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for LanguageBackend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        #[allow(deprecated)]
+        let folders = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) => folders.into_iter().map(|folder| folder.uri).collect(),
+            (None, Some(root)) => vec![root],
+            (None, None) => vec![],
+        };
+
+        let folders: Vec<_> = folders
+            .into_iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+            .collect();
+        for folder in folders.iter() {
+            self.workspace_mut().add_folder(folder.clone());
+        }
+
+        // Reading every script can take a while, and the editor is waiting to hear back.
+        let workspace = self.workspace.clone();
+        tokio::task::spawn_blocking(move || {
+            for script in folders.iter().flat_map(|folder| workspace::scripts(folder)) {
+                if let Ok(uri) = Url::from_file_path(script) {
+                    let mut workspace = workspace.write().expect("workspace lock was poisoned");
+                    workspace.read(uri);
+                }
+            }
+        });
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::INCREMENTAL,
+                )),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
-                completion_provider: Some(CompletionOptions::default()),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec!["[".into(), "#".into(), "@".into()]),
+                    ..Default::default()
+                }),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: semantic_tokens::legend(),
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                            ..Default::default()
+                        },
+                    ),
+                ),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: Default::default(),
+                })),
                 ..Default::default()
             },
             ..Default::default()
         })
     }
 
+    async fn initialized(&self, _: InitializedParams) {
+        // Scripts that aren't open can still change, like when switching branches.
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.nov".to_owned()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "watch-scripts".to_owned(),
+            method: "workspace/didChangeWatchedFiles".to_owned(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+
+        if let Err(error) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("Scripts changed outside the editor won't be noticed: {error}"),
+                )
+                .await;
+        }
+    }
+
     async fn shutdown(&self) -> Result<()> {
         Ok(())
     }
 
-    async fn hover(&self, _: HoverParams) -> Result<Option<Hover>> {
-        Ok(Some(Hover {
-            contents: HoverContents::Markup(MarkupContent {
-                kind: MarkupKind::Markdown,
-                value: "This is some `novel` text, yo!".to_owned(),
-            }),
-            range: None,
-        }))
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let document = params.text_document;
+        self.workspace_mut()
+            .open(document.uri.clone(), document.text, document.version);
+        self.publish_diagnostics(document.uri).await;
     }
 
-    async fn completion(&self, _: CompletionParams) -> Result<Option<CompletionResponse>> {
-        Ok(Some(CompletionResponse::Array(vec![
-            CompletionItem::new_simple("NEW SCENE".to_string(), "Creates a new scene.".to_string()),
-        ])))
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let document = params.text_document;
+        self.workspace_mut()
+            .change(&document.uri, document.version, params.content_changes);
+        self.publish_diagnostics(document.uri).await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.workspace_mut().close(&uri);
+        self.client.publish_diagnostics(uri, vec![], None).await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let mut workspace = self.workspace_mut();
+        for change in params.changes {
+            workspace.changed_on_disk(change.uri);
+        }
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        let hover = workspace
+            .get(&uri)
+            .and_then(|document| hover::hover(&workspace, &uri, document, position.position));
+
+        Ok(hover)
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let position = params.text_document_position;
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        let items = workspace
+            .get(&position.text_document.uri)
+            .and_then(|document| completion::completions(&workspace, document, position.position));
+
+        Ok(items.map(CompletionResponse::Array))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let position = params.text_document_position_params;
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        let location =
+            navigation::definition(&workspace, &position.text_document.uri, position.position);
+
+        Ok(location.map(GotoDefinitionResponse::Scalar))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let position = params.text_document_position;
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        Ok(navigation::references(
+            &workspace,
+            &position.text_document.uri,
+            position.position,
+            params.context.include_declaration,
+        ))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        Ok(navigation::prepare_rename(
+            &workspace,
+            &params.text_document.uri,
+            params.position,
+        ))
+    }
+
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let position = params.text_document_position;
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        navigation::rename(
+            &workspace,
+            &position.text_document.uri,
+            position.position,
+            &params.new_name,
+        )
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        let tokens =
+            self.with_document(&params.text_document.uri, semantic_tokens::semantic_tokens);
+
+        Ok(tokens.map(SemanticTokensResult::Tokens))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let edits = self.with_document(&params.text_document.uri, |document| {
+            // Formatting a document with syntax errors would drop whatever couldn't be parsed.
+            if !document.errors.is_empty() {
+                return None;
+            }
+
+            let dialect = crate::migrate::Dialect::of(&document.text);
+            let formatted = crate::formatter::format(&document.file, dialect);
+            Some(format_edits(document, &formatted))
+        });
+
+        Ok(edits.flatten())
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        Ok(self.with_document(&params.text_document.uri, code_actions::folding_ranges))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let workspace = self.workspace.read().expect("workspace lock was poisoned");
+
+        let actions = workspace.get(&uri).map(|document| {
+            code_actions::code_actions(&workspace, &uri, document, params.range)
+                .into_iter()
+                .map(CodeActionOrCommand::CodeAction)
+                .collect()
+        });
+
+        Ok(actions)
     }
 
     async fn document_symbol(
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
-        let symbol_1 = SymbolInformation {
-            name: "Scene".to_string(),
-            kind: SymbolKind::FUNCTION,
-            tags: None,
-            deprecated: None,
-            location: Location {
-                uri: Url::parse("localhost").unwrap(),
-                range: Range {
-                    start: Position {
-                        line: 0,
-                        character: 0,
-                    },
-                    end: Position {
-                        line: 0,
-                        character: 10,
-                    },
-                },
-            },
-            container_name: None,
+        let symbols = self.with_document(&params.text_document.uri, |document| {
+            let lines = document.lines();
+
+            document
+                .file
+                .scenes
+                .iter()
+                .enumerate()
+                .map(|(idx, scene)| scene_symbol(&lines, scene, idx))
+                .collect()
+        });
+
+        let Some(symbols) = symbols else {
+            return Ok(None);
         };
 
-        let symbols = vec![symbol_1];
-        let response = DocumentSymbolResponse::Flat(symbols);
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+}
+
+fn document_diagnostics(document: &Document) -> Vec<Diagnostic> {
+    let lines = document.lines();
+    let mut diagnostics = vec![];
+
+    for error in document.errors.iter() {
+        diagnostics.push(Diagnostic {
+            range: range(&lines, &error.diagnostic.span),
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("novel".to_owned()),
+            message: error.diagnostic.message(),
+            ..Default::default()
+        });
+
+        if !error.skipped.is_empty() {
+            diagnostics.push(Diagnostic {
+                range: range(&lines, &error.skipped),
+                severity: Some(DiagnosticSeverity::HINT),
+                source: Some("novel".to_owned()),
+                message: "This was skipped because of a syntax error.".to_owned(),
+                tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                ..Default::default()
+            });
+        }
+    }
+
+    for problem in Graph::new(&document.file).problems() {
+        let severity = match problem.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Note => continue,
+        };
+
+        diagnostics.push(Diagnostic {
+            range: range(&lines, &problem.span),
+            severity: Some(severity),
+            source: Some("novel".to_owned()),
+            message: problem.message,
+            ..Default::default()
+        });
+    }
+
+    diagnostics
+}
+
+/// The outline of a scene: its metadata, who speaks in it, and the tags used in it.
+fn scene_symbol(lines: &LineIndex, scene: &Spanned<Scene>, idx: usize) -> DocumentSymbol {
+    let name = scene.name_or_position(idx);
+    let selection = match &scene.name {
+        Some(name) => name.span.clone(),
+        None => scene.span.start..scene.span.start + "==".len(),
+    };
+
+    let meta = scene.meta.iter().map(|(key, value)| {
+        symbol(
+            lines,
+            key.to_string(),
+            Some(value.as_string()),
+            SymbolKind::PROPERTY,
+            key.span.start..value.span.end,
+            key.span.clone(),
+            vec![],
+        )
+    });
 
-        Ok(Some(response))
+    let mut speakers: Vec<(&str, Vec<DocumentSymbol>)> = vec![];
+    let mut tags: Vec<(&str, Vec<DocumentSymbol>)> = vec![];
+
+    for (item, referent) in lookup::items_with_speakers(scene) {
+        match &item.node {
+            SceneItem::DialogueBlock { speaker, block } => {
+                let line = block
+                    .iter()
+                    .find(|line| !line.is_parenthetical())
+                    .or(block.first())
+                    .map(RichText::as_string)
+                    .unwrap_or_default();
+                let child = symbol(
+                    lines,
+                    non_empty(line, "..."),
+                    None,
+                    SymbolKind::STRING,
+                    item.span.clone(),
+                    speaker.span.clone(),
+                    vec![],
+                );
+                group(&mut speakers, referent, child);
+            }
+            SceneItem::TaggedAction(tag, rich_text) => {
+                let child = symbol(
+                    lines,
+                    non_empty(rich_text.as_string(), tag),
+                    None,
+                    SymbolKind::EVENT,
+                    item.span.clone(),
+                    tag.span.clone(),
+                    vec![],
+                );
+                group(&mut tags, tag, child);
+            }
+            _ => {}
+        }
+    }
+
+    let speakers = speakers.into_iter().map(|(speaker, blocks)| {
+        group_symbol(
+            format!("[{speaker}]"),
+            format!("{} dialogue blocks", blocks.len()),
+            SymbolKind::OBJECT,
+            blocks,
+        )
+    });
+    let tags = tags.into_iter().map(|(tag, actions)| {
+        group_symbol(
+            format!("#{tag}"),
+            format!("{} tagged actions", actions.len()),
+            SymbolKind::KEY,
+            actions,
+        )
+    });
+
+    symbol(
+        lines,
+        name,
+        None,
+        SymbolKind::MODULE,
+        scene.span.clone(),
+        selection,
+        meta.chain(speakers).chain(tags).collect(),
+    )
+}
+
+fn group<'a>(
+    groups: &mut Vec<(&'a str, Vec<DocumentSymbol>)>,
+    key: &'a str,
+    child: DocumentSymbol,
+) {
+    match groups.iter_mut().find(|(group, _)| *group == key) {
+        Some((_, children)) => children.push(child),
+        None => groups.push((key, vec![child])),
+    }
+}
+
+/// A symbol spanning all of its (non-empty) `children`.
+fn group_symbol(
+    name: String,
+    detail: String,
+    kind: SymbolKind,
+    children: Vec<DocumentSymbol>,
+) -> DocumentSymbol {
+    let start = children.iter().map(|child| child.range.start).min();
+    let end = children.iter().map(|child| child.range.end).max();
+    let range = Range::new(start.unwrap_or_default(), end.unwrap_or_default());
+
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name,
+        detail: Some(detail),
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range: children
+            .first()
+            .map_or(range, |child| child.selection_range),
+        children: Some(children),
     }
 }
+
+fn symbol(
+    lines: &LineIndex,
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    span: Span,
+    selection: Span,
+    children: Vec<DocumentSymbol>,
+) -> DocumentSymbol {
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name,
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: range(lines, &span),
+        selection_range: range(lines, &selection),
+        children: (!children.is_empty()).then_some(children),
+    }
+}
+
+/// Editors refuse symbols with empty names.
+fn non_empty(name: String, fallback: &str) -> String {
+    match name.trim() {
+        "" => fallback.to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+/// The edits that turn a document into its formatted text, one for each scene that changed.
+///
+/// The text is split before every scene, and the formatter writes every scene there was,
+/// so each piece of the document can be compared with the same piece of the formatted text.
+fn format_edits(document: &Document, formatted: &str) -> Vec<TextEdit> {
+    fn pieces(text: &str, tree: &SyntaxNode) -> Vec<Span> {
+        let mut starts: Vec<usize> = tree
+            .children()
+            .filter(|child| child.kind() == SyntaxKind::Scene)
+            .map(|scene| scene.span().start)
+            .collect();
+        starts.insert(0, 0);
+        starts.push(text.len());
+        starts.windows(2).map(|pair| pair[0]..pair[1]).collect()
+    }
+
+    let lines = document.lines();
+    let everything = || {
+        vec![TextEdit::new(
+            range(&lines, &(0..document.text.len())),
+            formatted.to_owned(),
+        )]
+    };
+
+    let Ok(formatted_tree) = crate::parser::parse_tree(formatted) else {
+        return everything();
+    };
+    let old = pieces(&document.text, &document.syntax());
+    let new = pieces(formatted, &SyntaxNode::new_root(formatted_tree));
+    if old.len() != new.len() {
+        return everything();
+    }
+
+    old.into_iter()
+        .zip(new)
+        .filter(|(old, new)| document.text[old.clone()] != formatted[new.clone()])
+        .map(|(old, new)| TextEdit::new(range(&lines, &old), formatted[new].to_owned()))
+        .collect()
+}
+
+fn range(lines: &LineIndex, span: &Span) -> Range {
+    Range::new(position(lines, span.start), position(lines, span.end))
+}
+
+fn position(lines: &LineIndex, offset: usize) -> Position {
+    let LineColumn { line, column } = lines.line_column_utf16(offset);
+    Position::new(line as u32, column as u32)
+}
@@ -0,0 +1,287 @@
+use super::lookup;
+use super::workspace::{Document, Workspace};
+use crate::types::{RichTextPart, Scene, SceneItem, Span, Spanned};
+use std::collections::HashMap;
+use tower_lsp::lsp_types::*;
+
+/// Every region worth folding: scenes, dialogue blocks and `@IF` blocks.
+pub fn folding_ranges(document: &Document) -> Vec<FoldingRange> {
+    let lines = document.lines();
+    let mut ranges = vec![];
+    let mut fold = |span: &Span| {
+        let text = document.text[span.clone()].trim_end();
+        let start = lines.line_column(span.start).line;
+        let end = lines.line_column(span.start + text.len()).line;
+
+        if end > start {
+            ranges.push(FoldingRange {
+                start_line: start as u32,
+                end_line: end as u32,
+                kind: Some(FoldingRangeKind::Region),
+                ..Default::default()
+            });
+        }
+    };
+
+    for scene in document.file.scenes.iter() {
+        fold(&scene.span);
+
+        for item in scene.all_items() {
+            if let SceneItem::DialogueBlock { .. } | SceneItem::If(..) = &item.node {
+                fold(&item.span);
+            }
+        }
+    }
+
+    ranges
+}
+
+/// The actions that make sense for the selected `range` of a document.
+pub fn code_actions(
+    workspace: &Workspace,
+    uri: &Url,
+    document: &Document,
+    range: Range,
+) -> Vec<CodeAction> {
+    let start = document.offset(range.start);
+    let end = document.offset(range.end);
+    let Some(scene) = document
+        .file
+        .scenes
+        .iter()
+        .find(|scene| scene.span.start <= start && start <= scene.span.end)
+    else {
+        return vec![];
+    };
+
+    let edit = |span: Span, new_text: String| {
+        let range = super::range(&document.lines(), &span);
+        let changes = HashMap::from([(uri.clone(), vec![TextEdit::new(range, new_text)])]);
+        Some(WorkspaceEdit::new(changes))
+    };
+    let action = |title: String, kind: CodeActionKind, edit: Option<WorkspaceEdit>| CodeAction {
+        title,
+        kind: Some(kind),
+        edit,
+        ..Default::default()
+    };
+
+    let mut actions = vec![];
+
+    for (item, referent) in lookup::items_with_speakers(scene) {
+        let SceneItem::DialogueBlock { speaker, .. } = &item.node else {
+            continue;
+        };
+        if speaker.referent != "&" || referent == "&" {
+            continue;
+        }
+
+        // A block without a speaker has one made up, spanning nothing, so it gets one written above it.
+        let (selected, span, new_text) = match speaker.span.is_empty() {
+            true => {
+                let line_start = document.text[..item.span.start]
+                    .rfind('\n')
+                    .map_or(0, |idx| idx + 1);
+                let new_text = format!("[{referent}]\n");
+                (&item.span, line_start..line_start, new_text)
+            }
+            false => (&speaker.span, speaker.span.clone(), referent.to_owned()),
+        };
+        if selected.start <= end && start <= selected.end {
+            actions.push(action(
+                format!("Write out the previous speaker, `{referent}`"),
+                CodeActionKind::QUICKFIX,
+                edit(span, new_text),
+            ));
+        }
+    }
+
+    // Without a header, the metadata would be read as the frontmatter's instead.
+    let header = lookup::header_span(&document.text, scene);
+    if !scene.meta.contains_key("Summary") && !header.is_empty() {
+        // An empty value would be read as the start of the scene, not as its metadata.
+        let after_header = document.text[header.end..]
+            .find('\n')
+            .map_or(document.text.len(), |idx| header.end + idx + 1);
+        let new_text = match after_header == document.text.len() && !document.text.ends_with('\n') {
+            true => "\nSummary: TODO",
+            false => "Summary: TODO\n",
+        };
+
+        actions.push(action(
+            "Add a `Summary`".to_owned(),
+            CodeActionKind::QUICKFIX,
+            edit(after_header..after_header, new_text.to_owned()),
+        ));
+    }
+
+    if let Some((span, name)) = bare_name(workspace, document, scene, start, end) {
+        actions.push(action(
+            format!("Turn `{name}` into a reference"),
+            CodeActionKind::QUICKFIX,
+            edit(span, format!("[[{name}]]")),
+        ));
+    }
+
+    // Splitting only makes sense between items, so the cursor has to be on the first line of one.
+    let line_start = document.text[..start].rfind('\n').map_or(0, |idx| idx + 1);
+    if let Some(item) = scene
+        .items
+        .iter()
+        .find(|item| item.span.start == line_start)
+        && item.span.start > scene.items[0].span.start
+    {
+        let name = match &scene.name {
+            Some(name) => format!("{} (continued)", name.node),
+            None => "New Scene".to_owned(),
+        };
+
+        actions.push(action(
+            "Split the scene here".to_owned(),
+            CodeActionKind::REFACTOR,
+            edit(
+                item.span.start..item.span.start,
+                format!("== {name} ==\n\n"),
+            ),
+        ));
+    }
+
+    actions
+}
+
+/// A name of a scene or character, written as plain text where the selection is.
+///
+/// With nothing selected, it is the name around the cursor. Otherwise, it is the selection itself.
+fn bare_name(
+    workspace: &Workspace,
+    document: &Document,
+    scene: &Spanned<Scene>,
+    start: usize,
+    end: usize,
+) -> Option<(Span, String)> {
+    let part = scene
+        .all_items()
+        .into_iter()
+        .flat_map(|item| item.rich_texts())
+        .flat_map(|rich_text| rich_text.0.iter())
+        .find(|part| part.span.start <= start && end <= part.span.end)?;
+    if !matches!(part.node, RichTextPart::Text(_)) {
+        return None;
+    }
+
+    if start < end {
+        let selection = &document.text[start..end];
+        let name = selection.trim();
+        let offset = start + (selection.len() - selection.trim_start().len());
+        return (!name.is_empty()).then(|| (offset..offset + name.len(), name.to_owned()));
+    }
+
+    let files = || lookup::files(workspace).map(|(_, file)| file);
+    let names = lookup::scenes(files())
+        .into_iter()
+        .chain(lookup::speakers(files()));
+    let text = &document.text[part.span.clone()];
+    let is_boundary = |idx: usize| {
+        let before = text[..idx].chars().next_back();
+        let after = text[idx..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) || !after.is_some_and(char::is_alphanumeric)
+    };
+
+    for name in names {
+        for (idx, _) in text.match_indices(name.as_str()) {
+            let span = part.span.start + idx..part.span.start + idx + name.len();
+            if span.start <= start
+                && start <= span.end
+                && is_boundary(idx)
+                && is_boundary(idx + name.len())
+            {
+                return Some((span, name));
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Value;
+
+    /// The text of the document at `uri` after applying the action called `title`.
+    fn applied(workspace: &Workspace, uri: &Url, offset: usize, title: &str) -> String {
+        let document = workspace.get(uri).unwrap();
+        let position = super::super::position(&document.lines(), offset);
+        let range = Range::new(position, position);
+
+        let action = code_actions(workspace, uri, document, range)
+            .into_iter()
+            .find(|action| action.title == title)
+            .unwrap_or_else(|| panic!("no action called {title:?}"));
+        let mut edits = action.edit.unwrap().changes.unwrap().remove(uri).unwrap();
+        edits.sort_by_key(|edit| edit.range.start);
+
+        let mut text = document.text.clone();
+        for edit in edits.iter().rev() {
+            let range = document.offset(edit.range.start)..document.offset(edit.range.end);
+            text.replace_range(range, &edit.new_text);
+        }
+        text
+    }
+
+    #[test]
+    fn summaries_are_added_to_the_metadata() {
+        let uri = Url::parse("file:///script.nov").unwrap();
+
+        for script in [
+            "T\n\n== A ==\nWhere: [Forest]\n\nHi.\n",
+            "T\n\n== A ==\n\nHi.\n",
+            "T\n\n== A ==",
+        ] {
+            let mut workspace = Workspace::default();
+            workspace.open(uri.clone(), script.to_owned(), 0);
+
+            let text = applied(
+                &workspace,
+                &uri,
+                script.find("A").unwrap(),
+                "Add a `Summary`",
+            );
+            let file = crate::parser::parse(&text).unwrap();
+            let (before, _) = crate::parser::parse_recovering(script);
+
+            let scene = &file.scenes[0];
+            let summary = scene.meta.get("Summary").map(|value| &value.node);
+            assert!(
+                matches!(summary, Some(Value::RichText(text)) if text.as_string() == "TODO"),
+                "{text:?} has the summary {summary:?}"
+            );
+            assert_eq!(scene.items, before.scenes[0].items, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn previous_speakers_are_written_out() {
+        let uri = Url::parse("file:///script.nov").unwrap();
+        let title = "Write out the previous speaker, `Claire`";
+
+        for (script, at, expected) in [
+            (
+                "T\n\n== A ==\n\n[Claire]\nHi.\n\n[&]\nOkay.\n",
+                "&",
+                "T\n\n== A ==\n\n[Claire]\nHi.\n\n[Claire]\nOkay.\n",
+            ),
+            (
+                "T\n\n== A ==\n\n[Claire]\n- Hi.\n\n- Okay.\n",
+                "Okay",
+                "T\n\n== A ==\n\n[Claire]\n- Hi.\n\n[Claire]\n- Okay.\n",
+            ),
+        ] {
+            let mut workspace = Workspace::default();
+            workspace.open(uri.clone(), script.to_owned(), 0);
+
+            let offset = script.find(at).unwrap();
+            assert_eq!(applied(&workspace, &uri, offset, title), expected);
+        }
+    }
+}
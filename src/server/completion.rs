@@ -0,0 +1,316 @@
+use super::lookup::{scenes, speakers};
+use super::workspace::{Document, Workspace};
+use crate::types::{File, SceneItem};
+use std::collections::BTreeSet;
+use tower_lsp::lsp_types::*;
+
+/// Metadata keys worth suggesting even before they are used anywhere.
+const COMMON_META_KEYS: &[&str] = &[
+    "Where",
+    "When",
+    "Summary",
+    "Music",
+    "Characters",
+    "Tags",
+    "Board",
+    "Author",
+];
+
+const DIRECTIVES: &[(&str, &str)] = &[
+    ("CONT", "Continues to another scene."),
+    ("IF", "Only happens under a condition."),
+    ("SET", "Sets a flag or a variable."),
+];
+
+/// What is being written at the cursor.
+#[derive(Debug, PartialEq)]
+enum Context {
+    /// A speaker, after `[`.
+    Speaker,
+    /// A reference, after `[[`.
+    Reference,
+    /// A tag, after `#`.
+    Tag,
+    /// A directive, after `@`.
+    Directive,
+    /// The scene an `@CONT` continues to.
+    ContTarget,
+    /// A key in the metadata right after a scene header.
+    MetaKey,
+}
+
+/// Suggests what could be written at `position`, based on what is already written in the workspace.
+pub fn completions(
+    workspace: &Workspace,
+    document: &Document,
+    position: Position,
+) -> Option<Vec<CompletionItem>> {
+    let offset = document.offset(position);
+    let (context, start) = context(document, offset)?;
+    let files = || workspace.documents().map(|(_, document)| &document.file);
+
+    let suggestions: Vec<(String, CompletionItemKind, Option<String>)> = match context {
+        Context::Speaker => speakers(files())
+            .into_iter()
+            .map(|speaker| (speaker, CompletionItemKind::CLASS, None))
+            .collect(),
+        Context::Reference => {
+            let scenes = scenes(files()).into_iter();
+            let speakers = speakers(files()).into_iter();
+            let referents = referents(files()).into_iter();
+
+            scenes
+                .map(|scene| (scene, CompletionItemKind::MODULE, Some("Scene".to_owned())))
+                .chain(speakers.map(|speaker| {
+                    (
+                        speaker,
+                        CompletionItemKind::CLASS,
+                        Some("Speaker".to_owned()),
+                    )
+                }))
+                .chain(referents.map(|referent| (referent, CompletionItemKind::REFERENCE, None)))
+                .collect()
+        }
+        Context::Tag => tags(files())
+            .into_iter()
+            .map(|tag| (tag, CompletionItemKind::KEYWORD, None))
+            .collect(),
+        Context::Directive => DIRECTIVES
+            .iter()
+            .map(|(directive, detail)| {
+                let detail = Some(detail.to_string());
+                (directive.to_string(), CompletionItemKind::KEYWORD, detail)
+            })
+            .collect(),
+        Context::ContTarget => scenes(files())
+            .into_iter()
+            .map(|scene| (scene, CompletionItemKind::MODULE, None))
+            .collect(),
+        Context::MetaKey => {
+            let scene = document
+                .file
+                .scenes
+                .iter()
+                .find(|scene| scene.span.contains(&offset));
+
+            meta_keys(files())
+                .into_iter()
+                .filter(|key| scene.is_none_or(|scene| !scene.meta.contains_key(key.as_str())))
+                .map(|key| (format!("{key}: "), CompletionItemKind::PROPERTY, None))
+                .collect()
+        }
+    };
+
+    let lines = document.lines();
+    let range = super::range(&lines, &(start..offset));
+    let mut seen = BTreeSet::new();
+
+    let items = suggestions
+        .into_iter()
+        .filter(|(label, _, _)| seen.insert(label.clone()))
+        .map(|(label, kind, detail)| CompletionItem {
+            filter_text: Some(label.clone()),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                range,
+                label.clone(),
+            ))),
+            label,
+            kind: Some(kind),
+            detail,
+            ..Default::default()
+        })
+        .collect();
+
+    Some(items)
+}
+
+/// Finds out what is being written at `offset` from the line it is in,
+/// along with the offset where what is being written started.
+fn context(document: &Document, offset: usize) -> Option<(Context, usize)> {
+    let text = &document.text;
+    let line_start = text[..offset].rfind('\n').map_or(0, |idx| idx + 1);
+    let line = &text[line_start..offset];
+    let trimmed = line.trim_start();
+    let indent = line_start + (line.len() - trimmed.len());
+    let is_word = |text: &str| text.chars().all(char::is_alphanumeric);
+
+    if let Some(idx) = line.rfind("[[")
+        && !line[idx..].contains("]]")
+    {
+        return Some((Context::Reference, line_start + idx + "[[".len()));
+    }
+
+    if let Some(speaker) = trimmed.strip_prefix('[')
+        && !speaker.contains(']')
+    {
+        return Some((Context::Speaker, indent + "[".len()));
+    }
+
+    if let Some(tag) = trimmed.strip_prefix('#')
+        && is_word(tag)
+    {
+        return Some((Context::Tag, indent + "#".len()));
+    }
+
+    if let Some(tag) = trimmed.strip_prefix('>')
+        && is_word(tag.trim_start())
+    {
+        return Some((Context::Tag, offset - tag.trim_start().len()));
+    }
+
+    if let Some(idx) = line.rfind("@CONT ") {
+        let target = line[idx + "@CONT ".len()..].trim_start();
+        return Some((Context::ContTarget, offset - target.len()));
+    }
+
+    if let Some(idx) = line.rfind('@')
+        && line[..idx].chars().last().is_none_or(char::is_whitespace)
+        && is_word(&line[idx + 1..])
+    {
+        return Some((Context::Directive, line_start + idx + 1));
+    }
+
+    if is_word(line) && is_in_meta(&text[..line_start]) {
+        return Some((Context::MetaKey, line_start));
+    }
+
+    None
+}
+
+/// Whether a line following `before` would be in the metadata of a scene,
+/// meaning only metadata separates it from the scene header.
+fn is_in_meta(before: &str) -> bool {
+    for line in before.lines().rev() {
+        if line.starts_with("==") {
+            return true;
+        }
+
+        let is_meta = line.split_once(':').is_some_and(|(key, _)| {
+            !key.is_empty() && key.trim_end().chars().all(char::is_alphanumeric)
+        });
+        if !is_meta {
+            return false;
+        }
+    }
+
+    false
+}
+
+fn referents<'a>(files: impl Iterator<Item = &'a File>) -> BTreeSet<String> {
+    files
+        .flat_map(|file| file.scenes.iter())
+        .flat_map(|scene| scene.all_items())
+        .flat_map(|item| item.rich_texts())
+        .flat_map(|rich_text| rich_text.references())
+        .map(|reference| reference.referent.clone())
+        .collect()
+}
+
+fn tags<'a>(files: impl Iterator<Item = &'a File>) -> BTreeSet<String> {
+    files
+        .flat_map(|file| file.scenes.iter())
+        .flat_map(|scene| scene.all_items())
+        .filter_map(|item| match &item.node {
+            SceneItem::TaggedAction(tag, _) => Some(tag.node.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn meta_keys<'a>(files: impl Iterator<Item = &'a File>) -> Vec<String> {
+    let used: BTreeSet<String> = files
+        .flat_map(|file| file.scenes.iter())
+        .flat_map(|scene| scene.meta.keys())
+        .map(|key| key.node.clone())
+        .collect();
+
+    COMMON_META_KEYS
+        .iter()
+        .map(|key| key.to_string())
+        .chain(used)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What is being written at the end of `before`, and the part of it written so far.
+    fn context_at(before: &str, after: &str) -> Option<(Context, String)> {
+        let document = Document::new(format!("{before}{after}"), 0);
+        let (context, start) = context(&document, before.len())?;
+        Some((context, before[start..].to_owned()))
+    }
+
+    fn is(context: Context, written: &str) -> Option<(Context, String)> {
+        Some((context, written.to_owned()))
+    }
+
+    #[test]
+    fn speakers_are_written_at_the_start_of_a_line() {
+        assert_eq!(
+            context_at("T\n\n====\n\n[Cla", ""),
+            is(Context::Speaker, "Cla")
+        );
+        assert_eq!(
+            context_at("T\n\n====\n\n    [", ""),
+            is(Context::Speaker, "")
+        );
+        assert_eq!(context_at("T\n\n====\n\n[Claire] ", ""), None);
+    }
+
+    #[test]
+    fn references_are_written_anywhere_in_a_line() {
+        let before = "T\n\n====\n\nThey find [[Tree";
+        assert_eq!(
+            context_at(before, "]] there."),
+            is(Context::Reference, "Tree")
+        );
+        assert_eq!(
+            context_at("[[Tree]] and [[", ""),
+            is(Context::Reference, "")
+        );
+        assert_eq!(context_at("[[Tree]] and more", ""), None);
+    }
+
+    #[test]
+    fn tags_follow_a_hash_or_an_angle_bracket() {
+        assert_eq!(context_at("#SF", ""), is(Context::Tag, "SF"));
+        assert_eq!(context_at("> SF", ""), is(Context::Tag, "SF"));
+        assert_eq!(context_at("#SFX Loud", ""), None);
+    }
+
+    #[test]
+    fn directives_follow_an_at_sign() {
+        assert_eq!(context_at("@CO", ""), is(Context::Directive, "CO"));
+        assert_eq!(context_at("@IF Apple @", ""), is(Context::Directive, ""));
+        assert_eq!(context_at("someone@CO", ""), None);
+    }
+
+    #[test]
+    fn cont_targets_follow_a_cont() {
+        assert_eq!(
+            context_at("@CONT Scene 0", ""),
+            is(Context::ContTarget, "Scene 0")
+        );
+        assert_eq!(
+            context_at("@IF Apple @CONT App", ""),
+            is(Context::ContTarget, "App")
+        );
+    }
+
+    #[test]
+    fn meta_keys_are_only_written_right_after_a_header() {
+        let header = "T\n\n== A ==\nWhere: [Forest]\n";
+        assert_eq!(
+            context_at(&format!("{header}Wh"), ""),
+            is(Context::MetaKey, "Wh")
+        );
+        assert_eq!(
+            context_at(&format!("{header}\nWh"), ""),
+            None,
+            "a blank line ends the metadata"
+        );
+        assert_eq!(context_at("T\n\nWh", ""), None);
+    }
+}
@@ -0,0 +1,101 @@
+use super::lookup::{self, Symbol, Target};
+use super::workspace::{Document, Workspace};
+use crate::types::{Scene, SceneItem};
+use tower_lsp::lsp_types::*;
+
+/// The meta shown when hovering a scene, in order.
+const SCENE_META_KEYS: &[&str] = &["Where", "When"];
+
+/// Describes the symbol at `position`.
+pub fn hover(
+    workspace: &Workspace,
+    uri: &Url,
+    document: &Document,
+    position: Position,
+) -> Option<Hover> {
+    let symbol = lookup::symbol_at(document, document.offset(position))?;
+
+    let value = match &symbol {
+        Symbol::Scene(scene) => describe_scene(scene),
+        Symbol::Speaker { referent, .. } => describe_character(workspace, referent),
+        Symbol::Reference(reference) | Symbol::Cont(reference) => {
+            match lookup::resolve(workspace, uri, &reference.referent) {
+                Target::Scene(_, scene) => describe_scene(scene),
+                Target::Character { name, .. } => describe_character(workspace, name),
+                Target::File(url) => format!("File `{url}`"),
+                Target::Unknown => format!(
+                    "`{}` doesn't refer to any scene or character.",
+                    reference.referent
+                ),
+            }
+        }
+    };
+
+    let lines = document.lines();
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: Some(super::range(&lines, &symbol.span(&document.text))),
+    })
+}
+
+fn describe_scene(scene: &Scene) -> String {
+    let name = scene
+        .name
+        .as_deref()
+        .map_or("Unnamed scene", String::as_str);
+    let mut sections = vec![format!("**{name}**")];
+
+    if let Some(summary) = scene.meta.get("Summary") {
+        sections.push(summary.as_string());
+    }
+
+    let meta: Vec<String> = SCENE_META_KEYS
+        .iter()
+        .filter_map(|key| Some(format!("*{key}:* {}", scene.meta.get(*key)?.as_string())))
+        .collect();
+    if !meta.is_empty() {
+        sections.push(meta.join("  \n"));
+    }
+
+    sections.join("\n\n")
+}
+
+/// How much someone speaks, and where.
+fn describe_character(workspace: &Workspace, name: &str) -> String {
+    let mut lines = 0;
+    let mut scenes = vec![];
+
+    for (_, file) in lookup::files(workspace) {
+        for (idx, scene) in file.scenes.iter().enumerate() {
+            let mut appears = false;
+
+            for (item, speaker) in lookup::items_with_speakers(scene) {
+                if let SceneItem::DialogueBlock { block, .. } = &item.node
+                    && speaker == name
+                {
+                    lines += block.iter().filter(|line| !line.is_parenthetical()).count();
+                    appears = true;
+                }
+            }
+
+            if appears {
+                scenes.push(scene.name_or_position(idx));
+            }
+        }
+    }
+
+    let lines = match lines {
+        1 => "1 line".to_owned(),
+        n => format!("{n} lines"),
+    };
+    let scenes = match scenes.len() {
+        0 => String::new(),
+        1 => format!(" in 1 scene: {}", scenes[0]),
+        n => format!(" in {n} scenes: {}", scenes.join(", ")),
+    };
+
+    format!("**{name}**\n\n{lines}{scenes}")
+}
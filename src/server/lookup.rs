@@ -0,0 +1,326 @@
+use super::workspace::{Document, Workspace};
+use crate::types::{File, Reference, Scene, SceneItem, Span, Spanned};
+use std::collections::BTreeSet;
+use tower_lsp::lsp_types::Url;
+
+/// Something written in a document that refers to, or can be referred to by, a name.
+pub enum Symbol<'a> {
+    /// The header of a scene.
+    Scene(&'a Spanned<Scene>),
+    /// The speaker of a dialogue block, with `[&]` already resolved to the previous speaker.
+    Speaker {
+        reference: &'a Spanned<Reference>,
+        referent: &'a str,
+    },
+    /// A `[[reference]]` in rich text.
+    Reference(&'a Spanned<Reference>),
+    /// The scene an `@CONT` continues to.
+    Cont(&'a Spanned<Reference>),
+}
+
+impl<'a> Symbol<'a> {
+    /// The name the symbol goes by, if it has one.
+    pub fn name(&self) -> Option<&'a str> {
+        match self {
+            Symbol::Scene(scene) => scene.name.as_deref().map(String::as_str),
+            Symbol::Speaker { referent, .. } => Some(*referent).filter(|referent| *referent != "&"),
+            Symbol::Reference(reference) | Symbol::Cont(reference) => Some(&reference.referent),
+        }
+    }
+
+    /// The span of the name the symbol goes by, if it has one written down.
+    pub fn name_span(&self) -> Option<Span> {
+        match self {
+            Symbol::Scene(scene) => scene.name.as_ref().map(|name| name.span.clone()),
+            Symbol::Speaker { reference, .. } if reference.referent == "&" => None,
+            Symbol::Speaker { reference, .. }
+            | Symbol::Reference(reference)
+            | Symbol::Cont(reference) => Some(referent_span(reference)),
+        }
+    }
+
+    /// The span of the whole symbol.
+    pub fn span(&self, text: &str) -> Span {
+        match self {
+            Symbol::Scene(scene) => header_span(text, scene),
+            Symbol::Speaker { reference, .. }
+            | Symbol::Reference(reference)
+            | Symbol::Cont(reference) => reference.span.clone(),
+        }
+    }
+}
+
+/// What a name refers to.
+pub enum Target<'a> {
+    Scene(&'a Url, &'a Spanned<Scene>),
+    /// Someone who speaks somewhere in the workspace, along with the first time they do.
+    Character {
+        name: &'a str,
+        uri: &'a Url,
+        speaker: &'a Spanned<Reference>,
+    },
+    /// Another file, relative to the document the name was written in.
+    File(Url),
+    Unknown,
+}
+
+/// Finds the symbol at `offset` in a document.
+pub fn symbol_at(document: &Document, offset: usize) -> Option<Symbol<'_>> {
+    let contains = |span: &Span| span.start <= offset && offset <= span.end;
+
+    let frontmatter = document
+        .file
+        .frontmatter
+        .iter()
+        .flat_map(|meta| meta.values());
+    let mut in_frontmatter = frontmatter.flat_map(|value| value.references());
+    if let Some(reference) = in_frontmatter.find(|reference| contains(&reference.span)) {
+        return Some(Symbol::Reference(reference));
+    }
+
+    for scene in document.file.scenes.iter() {
+        let header = header_span(&document.text, scene);
+        if !header.is_empty() && contains(&header) {
+            return Some(Symbol::Scene(scene));
+        }
+        if !contains(&scene.span) {
+            continue;
+        }
+
+        let meta_references = scene.meta.values().flat_map(|value| value.references());
+        if let Some(reference) = meta_references.into_iter().find(|r| contains(&r.span)) {
+            return Some(Symbol::Reference(reference));
+        }
+
+        for (item, referent) in items_with_speakers(scene) {
+            match &item.node {
+                // A block without a speaker has one made up, which spans nothing written.
+                SceneItem::DialogueBlock { speaker, .. }
+                    if !speaker.span.is_empty() && contains(&speaker.span) =>
+                {
+                    return Some(Symbol::Speaker {
+                        reference: speaker,
+                        referent,
+                    });
+                }
+                SceneItem::Cont(target) if contains(&target.span) => {
+                    return Some(Symbol::Cont(target));
+                }
+                SceneItem::Set(_, Some(value)) => {
+                    let references = value.references();
+                    if let Some(reference) = references.into_iter().find(|r| contains(&r.span)) {
+                        return Some(Symbol::Reference(reference));
+                    }
+                }
+                _ => {}
+            }
+
+            let references = item.rich_texts().into_iter().flat_map(|r| r.references());
+            if let Some(reference) = references.into_iter().find(|r| contains(&r.span)) {
+                return Some(Symbol::Reference(reference));
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds what `referent`, written in the document at `uri`, refers to.
+///
+/// Scenes take precedence over characters, so `[[Intro]]` is the scene even if someone is called Intro.
+pub fn resolve<'a>(workspace: &'a Workspace, uri: &Url, referent: &'a str) -> Target<'a> {
+    for (scene_uri, document) in documents(workspace) {
+        let scene = document
+            .file
+            .scenes
+            .iter()
+            .find(|scene| scene.name.as_deref().is_some_and(|name| name == referent));
+        if let Some(scene) = scene {
+            return Target::Scene(scene_uri, scene);
+        }
+    }
+
+    for (speaker_uri, document) in documents(workspace) {
+        for scene in document.file.scenes.iter() {
+            for item in scene.all_items() {
+                if let SceneItem::DialogueBlock { speaker, .. } = &item.node
+                    && speaker.referent == referent
+                {
+                    return Target::Character {
+                        name: referent,
+                        uri: speaker_uri,
+                        speaker,
+                    };
+                }
+            }
+        }
+    }
+
+    if is_path(referent)
+        && let Ok(url) = Url::parse(referent).or_else(|_| uri.join(referent))
+    {
+        return Target::File(url);
+    }
+
+    Target::Unknown
+}
+
+/// A place where something is called by name.
+pub struct Occurrence<'a> {
+    pub uri: &'a Url,
+    /// The span of the name, without any alias after it.
+    pub span: Span,
+    /// Whether this is the header of the scene with that name, rather than a reference to it.
+    pub is_declaration: bool,
+}
+
+/// Every place in the workspace where `name` is used as a scene name or a referent.
+pub fn occurrences<'a>(workspace: &'a Workspace, name: &str) -> Vec<Occurrence<'a>> {
+    let mut occurrences = vec![];
+
+    for (uri, document) in documents(workspace) {
+        let frontmatter = document
+            .file
+            .frontmatter
+            .iter()
+            .flat_map(|meta| meta.values());
+        let in_frontmatter = frontmatter
+            .flat_map(|value| value.references())
+            .filter(|reference| reference.referent == name)
+            .map(|reference| Occurrence {
+                uri,
+                span: referent_span(reference),
+                is_declaration: false,
+            });
+        occurrences.extend(in_frontmatter);
+
+        for scene in document.file.scenes.iter() {
+            if let Some(scene_name) = &scene.name
+                && scene_name.node == name
+            {
+                occurrences.push(Occurrence {
+                    uri,
+                    span: scene_name.span.clone(),
+                    is_declaration: true,
+                });
+            }
+
+            let references = references(scene)
+                .into_iter()
+                .filter(|reference| reference.referent == name)
+                .map(|reference| Occurrence {
+                    uri,
+                    span: referent_span(reference),
+                    is_declaration: false,
+                });
+            occurrences.extend(references);
+        }
+    }
+
+    occurrences
+}
+
+/// Every reference in a scene, whether in its metadata, as a speaker, an `@CONT` target or in rich text,
+/// in source order.
+pub fn references(scene: &Scene) -> Vec<&Spanned<Reference>> {
+    let meta = scene.meta.values().flat_map(|value| value.references());
+    let items = scene.all_items().into_iter().flat_map(|item| {
+        let mut references = match &item.node {
+            SceneItem::DialogueBlock { speaker, .. } => vec![speaker],
+            SceneItem::Cont(target) => vec![target],
+            // Values have references of their own, besides the ones in their rich text.
+            SceneItem::Set(_, Some(value)) => return value.references(),
+            _ => vec![],
+        };
+        let rich_texts = item.rich_texts().into_iter();
+        references.extend(rich_texts.flat_map(|rich_text| rich_text.references()));
+        references
+    });
+
+    let mut references: Vec<_> = meta.chain(items).collect();
+    references.sort_by_key(|reference| reference.span.start);
+    references
+}
+
+/// The span of a reference's referent, leaving out its alias.
+pub fn referent_span(reference: &Spanned<Reference>) -> Span {
+    reference.span.start..reference.span.start + reference.referent.len()
+}
+
+/// Every document in the workspace, in a stable order.
+pub fn documents(workspace: &Workspace) -> Vec<(&Url, &Document)> {
+    let mut documents: Vec<_> = workspace.documents().collect();
+    documents.sort_by_key(|(uri, _)| *uri);
+    documents
+}
+
+/// Every parsed file in the workspace, in a stable order.
+pub fn files(workspace: &Workspace) -> impl Iterator<Item = (&Url, &File)> {
+    documents(workspace)
+        .into_iter()
+        .map(|(uri, document)| (uri, &document.file))
+}
+
+/// The names of everyone who speaks in `files`.
+pub fn speakers<'a>(files: impl Iterator<Item = &'a File>) -> BTreeSet<String> {
+    files
+        .flat_map(|file| file.scenes.iter())
+        .flat_map(|scene| scene.all_items())
+        .filter_map(|item| match &item.node {
+            SceneItem::DialogueBlock { speaker, .. } if speaker.referent != "&" => {
+                Some(speaker.referent.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The names of every scene in `files`.
+pub fn scenes<'a>(files: impl Iterator<Item = &'a File>) -> BTreeSet<String> {
+    files
+        .flat_map(|file| file.scenes.iter())
+        .filter_map(|scene| scene.name.as_deref().cloned())
+        .collect()
+}
+
+/// Every item in a scene, along with who was speaking when it was written.
+///
+/// For dialogue blocks, that is their own speaker, with `[&]` resolved to the previous one.
+pub fn items_with_speakers(scene: &Scene) -> Vec<(&Spanned<SceneItem>, &str)> {
+    let mut previous_speaker = "&";
+
+    scene
+        .all_items()
+        .into_iter()
+        .map(|item| {
+            if let SceneItem::DialogueBlock { speaker, .. } = &item.node
+                && speaker.referent != "&"
+            {
+                previous_speaker = &speaker.referent;
+            }
+            (item, previous_speaker)
+        })
+        .collect()
+}
+
+/// The span of a scene's header line, without the line ending,
+/// which spans nothing for the items before the first scene header.
+pub fn header_span(text: &str, scene: &Spanned<Scene>) -> Span {
+    let start = scene.span.start;
+    let line = &text[start..scene.span.end];
+    if !line.starts_with("==") {
+        return start..start;
+    }
+
+    let end = line.find('\n').map_or(line.len(), |idx| idx);
+    start..start + line[..end].trim_end().len()
+}
+
+fn is_path(referent: &str) -> bool {
+    referent.contains('/')
+        || referent.rsplit_once('.').is_some_and(|(name, extension)| {
+            !name.is_empty()
+                && !extension.is_empty()
+                && extension.chars().all(char::is_alphanumeric)
+        })
+}
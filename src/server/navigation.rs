@@ -0,0 +1,222 @@
+use super::lookup::{self, Target};
+use super::workspace::Workspace;
+use crate::types::Span;
+use std::collections::HashMap;
+use tower_lsp::jsonrpc;
+use tower_lsp::lsp_types::*;
+
+/// Where the symbol at `position` is defined: the header of a scene,
+/// the first line of a character, or another file.
+pub fn definition(workspace: &Workspace, uri: &Url, position: Position) -> Option<Location> {
+    let document = workspace.get(uri)?;
+    let symbol = lookup::symbol_at(document, document.offset(position))?;
+    let name = symbol.name()?;
+
+    match lookup::resolve(workspace, uri, name) {
+        Target::Scene(uri, scene) => {
+            let span = match &scene.name {
+                Some(name) => name.span.clone(),
+                None => scene.span.clone(),
+            };
+            location(workspace, uri, &span)
+        }
+        Target::Character { uri, speaker, .. } => {
+            location(workspace, uri, &lookup::referent_span(speaker))
+        }
+        Target::File(uri) => Some(Location::new(uri, Range::default())),
+        Target::Unknown => None,
+    }
+}
+
+/// Every place in the workspace where the symbol at `position` is called by its name.
+pub fn references(
+    workspace: &Workspace,
+    uri: &Url,
+    position: Position,
+    include_declaration: bool,
+) -> Option<Vec<Location>> {
+    let document = workspace.get(uri)?;
+    let symbol = lookup::symbol_at(document, document.offset(position))?;
+    let name = symbol.name()?;
+
+    let locations = lookup::occurrences(workspace, name)
+        .into_iter()
+        .filter(|occurrence| include_declaration || !occurrence.is_declaration)
+        .filter_map(|occurrence| location(workspace, occurrence.uri, &occurrence.span))
+        .collect();
+
+    Some(locations)
+}
+
+/// The name that would be renamed at `position`, if there is one.
+pub fn prepare_rename(
+    workspace: &Workspace,
+    uri: &Url,
+    position: Position,
+) -> Option<PrepareRenameResponse> {
+    let document = workspace.get(uri)?;
+    let symbol = lookup::symbol_at(document, document.offset(position))?;
+    let span = symbol.name_span()?;
+
+    Some(PrepareRenameResponse::RangeWithPlaceholder {
+        range: super::range(&document.lines(), &span),
+        placeholder: symbol.name()?.to_owned(),
+    })
+}
+
+/// Renames the symbol at `position` everywhere in the workspace, keeping the aliases it is shown with.
+pub fn rename(
+    workspace: &Workspace,
+    uri: &Url,
+    position: Position,
+    new_name: &str,
+) -> jsonrpc::Result<Option<WorkspaceEdit>> {
+    // These would end the name early, or break the reference apart.
+    if new_name.trim().is_empty() || new_name.contains(['[', ']', '|', '=', '\r', '\n']) {
+        return Err(jsonrpc::Error::invalid_params(format!(
+            "`{new_name}` is not a valid name."
+        )));
+    }
+
+    let Some(document) = workspace.get(uri) else {
+        return Ok(None);
+    };
+    let symbol = lookup::symbol_at(document, document.offset(position));
+    let Some(name) = symbol
+        .filter(|symbol| symbol.name_span().is_some())
+        .and_then(|symbol| symbol.name())
+    else {
+        return Ok(None);
+    };
+
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for occurrence in lookup::occurrences(workspace, name) {
+        if let Some(location) = location(workspace, occurrence.uri, &occurrence.span) {
+            changes
+                .entry(location.uri)
+                .or_default()
+                .push(TextEdit::new(location.range, new_name.trim().to_owned()));
+        }
+    }
+
+    Ok(Some(WorkspaceEdit::new(changes)))
+}
+
+fn location(workspace: &Workspace, uri: &Url, span: &Span) -> Option<Location> {
+    let document = workspace.get(uri)?;
+    let range = super::range(&document.lines(), span);
+    Some(Location::new(uri.clone(), range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "\
+T
+
+== Key ==
+
+[Claire]
+- Where is the [[Key|golden key]]?
+
+@CONT Key
+";
+
+    /// `script` after renaming what is at `offset` to `new_name`.
+    fn renamed_in(script: &str, offset: usize, new_name: &str) -> jsonrpc::Result<String> {
+        let uri = Url::parse("file:///script.nov").unwrap();
+        let mut workspace = Workspace::default();
+        workspace.open(uri.clone(), script.to_owned(), 0);
+        let document = workspace.get(&uri).unwrap();
+        let position = super::super::position(&document.lines(), offset);
+
+        let edit = rename(&workspace, &uri, position, new_name)?.unwrap();
+        let mut edits = edit.changes.unwrap().remove(&uri).unwrap();
+        edits.sort_by_key(|edit| edit.range.start);
+
+        let mut text = script.to_owned();
+        for edit in edits.iter().rev() {
+            let range = document.offset(edit.range.start)..document.offset(edit.range.end);
+            text.replace_range(range, &edit.new_text);
+        }
+        Ok(text)
+    }
+
+    fn renamed(offset: usize, new_name: &str) -> jsonrpc::Result<String> {
+        renamed_in(SCRIPT, offset, new_name)
+    }
+
+    #[test]
+    fn renaming_keeps_aliases() {
+        let offset = SCRIPT.find("Key|").unwrap();
+
+        assert_eq!(
+            renamed(offset, "Old Key").unwrap(),
+            "\
+T
+
+== Old Key ==
+
+[Claire]
+- Where is the [[Old Key|golden key]]?
+
+@CONT Old Key
+"
+        );
+    }
+
+    #[test]
+    fn renaming_from_the_header_renames_the_references() {
+        let offset = SCRIPT.find("Key ==").unwrap();
+
+        assert!(
+            renamed(offset, "Door")
+                .unwrap()
+                .contains("[[Door|golden key]]")
+        );
+    }
+
+    #[test]
+    fn renaming_from_an_alias_renames_what_it_stands_for() {
+        let offset = SCRIPT.find("golden").unwrap();
+
+        assert_eq!(
+            renamed(offset, "Door").unwrap(),
+            SCRIPT.replace("Key", "Door")
+        );
+    }
+
+    #[test]
+    fn names_that_would_break_references_are_refused() {
+        let offset = SCRIPT.find("Key|").unwrap();
+
+        for new_name in ["", " ", "Old|Key", "[Key]", "A = B", "Two\nLines"] {
+            assert!(renamed(offset, new_name).is_err(), "{new_name:?}");
+        }
+    }
+
+    #[test]
+    fn renaming_includes_the_frontmatter() {
+        let script = "\
+---
+Characters: [Claire], [Andy]
+---
+T
+
+====
+
+[Claire]
+- Hi.
+";
+        let from_speaker = script.find("Claire]\n-").unwrap();
+        let from_frontmatter = script.find("Claire]").unwrap();
+
+        for offset in [from_speaker, from_frontmatter] {
+            assert_eq!(
+                renamed_in(script, offset, "Clara").unwrap(),
+                script.replace("Claire", "Clara")
+            );
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use super::lookup;
+use super::workspace::Document;
+use crate::syntax::SyntaxKind;
+use crate::types::{FormattingTag, RichText, RichTextPart, SceneItem, Span, Spanned, Value};
+use tower_lsp::lsp_types::*;
+
+/// The kinds of tokens the server highlights, by their index in the legend.
+pub const TOKEN_TYPES: &[SemanticTokenType] = &[
+    // Scene headers.
+    SemanticTokenType::NAMESPACE,
+    // Metadata keys.
+    SemanticTokenType::PROPERTY,
+    // Metadata values and formatted text.
+    SemanticTokenType::STRING,
+    // Speakers.
+    SemanticTokenType::CLASS,
+    // Parentheticals.
+    SemanticTokenType::PARAMETER,
+    // `#TAG`s.
+    SemanticTokenType::DECORATOR,
+    // `//` comments.
+    SemanticTokenType::COMMENT,
+    // References, variables, and the targets of `@CONT`.
+    SemanticTokenType::VARIABLE,
+    // Formatting delimiters, like `**`.
+    SemanticTokenType::OPERATOR,
+    // Typed values, like numbers and dates.
+    SemanticTokenType::NUMBER,
+    // Directives, like `@CONT`.
+    SemanticTokenType::KEYWORD,
+];
+
+pub const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::new("bold"),
+    SemanticTokenModifier::new("italic"),
+    SemanticTokenModifier::new("underline"),
+    SemanticTokenModifier::new("strikethrough"),
+];
+
+const SCENE_HEADER: u32 = 0;
+const META_KEY: u32 = 1;
+const STRING: u32 = 2;
+const SPEAKER: u32 = 3;
+const PARENTHETICAL: u32 = 4;
+const TAG: u32 = 5;
+const COMMENT: u32 = 6;
+const REFERENCE: u32 = 7;
+const DELIMITER: u32 = 8;
+const TYPED_VALUE: u32 = 9;
+const KEYWORD: u32 = 10;
+
+const DECLARATION: u32 = 1 << 0;
+const BOLD: u32 = 1 << 1;
+const ITALIC: u32 = 1 << 2;
+const UNDERLINE: u32 = 1 << 3;
+const STRIKETHROUGH: u32 = 1 << 4;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// A span of source to highlight as some type of token.
+struct Token {
+    span: Span,
+    token_type: u32,
+    modifiers: u32,
+}
+
+/// Highlights everything that was parsed in a document.
+pub fn semantic_tokens(document: &Document) -> SemanticTokens {
+    let mut tokens = vec![];
+    let mut push = |span: Span, token_type: u32, modifiers: u32| {
+        tokens.push(Token {
+            span,
+            token_type,
+            modifiers,
+        })
+    };
+
+    for (key, value) in document.file.frontmatter.iter().flatten() {
+        push(key.span.clone(), META_KEY, 0);
+        value_tokens(value, &mut push);
+    }
+
+    // The AST leaves out the `@` and the keyword of directives, so they're taken from the tree.
+    for node in document.syntax().descendants() {
+        if let (Some(at), Some(keyword)) =
+            (node.token(SyntaxKind::At), node.token(SyntaxKind::Keyword))
+        {
+            push(at.span().start..keyword.span().end, KEYWORD, 0);
+        }
+    }
+
+    for scene in document.file.scenes.iter() {
+        push(
+            lookup::header_span(&document.text, scene),
+            SCENE_HEADER,
+            DECLARATION,
+        );
+
+        for (key, value) in scene.meta.iter() {
+            push(key.span.clone(), META_KEY, 0);
+            value_tokens(value, &mut push);
+        }
+
+        for item in scene.all_items() {
+            match &item.node {
+                SceneItem::ActionBlock(rich_text) | SceneItem::If(rich_text, _) => {
+                    rich_text_tokens(rich_text, None, 0, &mut push)
+                }
+                SceneItem::DialogueBlock { speaker, block } => {
+                    push(speaker.span.clone(), SPEAKER, 0);
+                    for line in block {
+                        let base = line.is_parenthetical().then_some(PARENTHETICAL);
+                        rich_text_tokens(line, base, 0, &mut push);
+                    }
+                }
+                SceneItem::SpoilerBlock(_) => push(item.span.clone(), COMMENT, 0),
+                SceneItem::TaggedAction(tag, rich_text) => {
+                    // The span of the tag leaves out the `#` or `>` before it.
+                    let marker = document.text[..tag.span.start].trim_end_matches([' ', '\t']);
+                    push(marker.len() - 1..tag.span.end, TAG, 0);
+                    rich_text_tokens(rich_text, None, 0, &mut push);
+                }
+                SceneItem::Cont(target) => push(target.span.clone(), REFERENCE, 0),
+                SceneItem::Set(name, value) => {
+                    push(name.span.clone(), REFERENCE, 0);
+                    if let Some(value) = value {
+                        value_tokens(value, &mut push);
+                    }
+                }
+            }
+        }
+    }
+
+    tokens.sort_by_key(|token| token.span.start);
+    encode(document, tokens)
+}
+
+fn value_tokens(value: &Spanned<Value>, push: &mut impl FnMut(Span, u32, u32)) {
+    match &value.node {
+        Value::RichText(rich_text) => rich_text_tokens(rich_text, Some(STRING), 0, push),
+        Value::List(values) => {
+            for value in values {
+                value_tokens(value, push);
+            }
+        }
+        Value::Reference(reference) => push(reference.span.clone(), REFERENCE, 0),
+        _ => push(value.span.clone(), TYPED_VALUE, 0),
+    }
+}
+
+/// Highlights the parts of some rich text, with plain text highlighted as `base`, if anything.
+fn rich_text_tokens(
+    rich_text: &RichText,
+    base: Option<u32>,
+    modifiers: u32,
+    push: &mut impl FnMut(Span, u32, u32),
+) {
+    for part in rich_text.0.iter() {
+        match &part.node {
+            RichTextPart::Text(_) => {
+                if let Some(base) = base {
+                    push(part.span.clone(), base, modifiers);
+                }
+            }
+            RichTextPart::Reference(reference) => {
+                push(reference.span.clone(), REFERENCE, modifiers)
+            }
+            RichTextPart::Variable(_) => push(part.span.clone(), REFERENCE, modifiers),
+            RichTextPart::FormattedSection(tag, content) => {
+                let Some(content_span) = content.span() else {
+                    continue;
+                };
+                push(part.span.start..content_span.start, DELIMITER, 0);
+                rich_text_tokens(
+                    content,
+                    Some(base.unwrap_or(STRING)),
+                    modifiers | formatting_modifiers(tag),
+                    push,
+                );
+                push(content_span.end..part.span.end, DELIMITER, 0);
+            }
+        }
+    }
+}
+
+fn formatting_modifiers(tag: &FormattingTag) -> u32 {
+    match tag {
+        FormattingTag::Bold => BOLD,
+        FormattingTag::Italic => ITALIC,
+        FormattingTag::BoldItalic => BOLD | ITALIC,
+        FormattingTag::Underline => UNDERLINE,
+        FormattingTag::Strikethrough => STRIKETHROUGH,
+        FormattingTag::Other(_) => 0,
+    }
+}
+
+/// Encodes tokens relative to each other, as the protocol wants,
+/// splitting the ones spanning many lines since not every editor supports those.
+fn encode(document: &Document, tokens: Vec<Token>) -> SemanticTokens {
+    let lines = document.lines();
+    let mut data = vec![];
+    let mut previous = Position::default();
+
+    for token in tokens {
+        let mut start = token.span.start;
+
+        for piece in document.text[token.span.clone()].split('\n') {
+            let trimmed = piece.trim_start();
+            let piece_start = start + (piece.len() - trimmed.len());
+            let text = trimmed.trim_end();
+            start += piece.len() + "\n".len();
+
+            if text.is_empty() {
+                continue;
+            }
+
+            let position = super::position(&lines, piece_start);
+            let delta_start = match position.line == previous.line {
+                true => position.character - previous.character,
+                false => position.character,
+            };
+
+            data.push(SemanticToken {
+                delta_line: position.line - previous.line,
+                delta_start,
+                length: text.encode_utf16().count() as u32,
+                token_type: token.token_type,
+                token_modifiers_bitset: token.modifiers,
+            });
+            previous = position;
+        }
+    }
+
+    SemanticTokens {
+        result_id: None,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens of `text` as `(delta_line, delta_start, length, token_type)`.
+    fn encoded(text: &str, tokens: &[(Span, u32)]) -> Vec<(u32, u32, u32, u32)> {
+        let document = Document::new(text.to_owned(), 0);
+        let tokens = tokens
+            .iter()
+            .map(|(span, token_type)| Token {
+                span: span.clone(),
+                token_type: *token_type,
+                modifiers: 0,
+            })
+            .collect();
+
+        encode(&document, tokens)
+            .data
+            .into_iter()
+            .map(|token| {
+                let SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length,
+                    token_type,
+                    ..
+                } = token;
+                (delta_line, delta_start, length, token_type)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokens_start_relative_to_the_one_before() {
+        let text = "ab cd\nef\n  gh ij";
+        let tokens = [(0..2, 0), (3..5, 1), (6..8, 2), (11..13, 3), (14..16, 4)];
+
+        assert_eq!(
+            encoded(text, &tokens),
+            [
+                (0, 0, 2, 0),
+                (0, 3, 2, 1),
+                (1, 0, 2, 2),
+                (1, 2, 2, 3),
+                (0, 3, 2, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_over_many_lines_are_split_without_their_indentation() {
+        let text = "// one\n    two\n\nthree";
+
+        assert_eq!(
+            encoded(text, &[(0..text.len(), COMMENT)]),
+            [(0, 0, 6, COMMENT), (1, 4, 3, COMMENT), (2, 0, 5, COMMENT)]
+        );
+    }
+
+    #[test]
+    fn positions_and_lengths_are_in_utf16() {
+        let text = "é 😀 [Maple]";
+        let maple = text.find("Maple").unwrap();
+
+        assert_eq!(
+            encoded(text, &[(3..7, STRING), (maple..maple + 5, REFERENCE)]),
+            [(0, 2, 2, STRING), (0, 4, 5, REFERENCE)]
+        );
+    }
+
+    #[test]
+    fn documents_are_highlighted_in_order() {
+        let text = "\
+---
+Cast: [Claire]
+---
+T
+
+== A ==
+Where: [Forest]
+
+[Claire]
+- Hi **there**.
+
+@IF Apple @SET Met
+";
+        let document = Document::new(text.to_owned(), 0);
+        let types: Vec<_> = semantic_tokens(&document)
+            .data
+            .iter()
+            .map(|token| (token.delta_line, token.token_type))
+            .collect();
+
+        assert_eq!(
+            types,
+            [
+                (1, META_KEY),
+                (0, REFERENCE),
+                (2, STRING),
+                (2, SCENE_HEADER),
+                (1, META_KEY),
+                (0, REFERENCE),
+                (2, SPEAKER),
+                (1, DELIMITER),
+                (0, STRING),
+                (0, DELIMITER),
+                (2, KEYWORD),
+                (0, KEYWORD),
+                (0, REFERENCE),
+            ]
+        );
+    }
+}
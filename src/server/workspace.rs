@@ -0,0 +1,284 @@
+use crate::diagnostic::{LineColumn, LineIndex, LineStarts};
+use crate::parser::{Edit, PendingShifts, RecoveredError};
+use crate::syntax::{GreenNode, SyntaxNode};
+use crate::types::File;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent, Url};
+
+/// The documents the editor has open, as they are in its buffers,
+/// and the other `.nov` files in the workspace folders, as they are on disk.
+#[derive(Default)]
+pub struct Workspace {
+    documents: HashMap<Url, Document>,
+    on_disk: HashMap<Url, Document>,
+    folders: Vec<PathBuf>,
+}
+
+impl Workspace {
+    /// Adds a workspace folder, whose `.nov` files are then read as they change on disk.
+    pub fn add_folder(&mut self, folder: PathBuf) {
+        self.folders.push(folder);
+    }
+
+    /// Reads a file again after it changed on disk, if it is one of the workspace's scripts.
+    pub fn changed_on_disk(&mut self, uri: Url) {
+        if self.is_indexed(&uri) {
+            self.read(uri);
+        }
+    }
+
+    /// Whether a file is one of the scripts in the workspace folders, which are read from disk
+    /// whether or not they're open, with the same rules as [`scripts`].
+    fn is_indexed(&self, uri: &Url) -> bool {
+        let Ok(path) = uri.to_file_path() else {
+            return false;
+        };
+
+        let is_in_folder = self.folders.iter().any(|folder| {
+            path.strip_prefix(folder).is_ok_and(|relative| {
+                let parent = relative.parent().unwrap_or(Path::new(""));
+                parent.iter().all(|folder| !is_skipped(folder))
+            })
+        });
+        let is_symlink = path
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_symlink());
+
+        is_in_folder && is_script(&path) && !is_symlink
+    }
+
+    /// Reads a file from disk, or forgets it if it is not there anymore.
+    pub fn read(&mut self, uri: Url) {
+        let text = uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| std::fs::read_to_string(path).ok());
+
+        match text {
+            Some(text) => self.on_disk.insert(uri, Document::new(text, 0)),
+            None => self.on_disk.remove(&uri),
+        };
+    }
+
+    pub fn open(&mut self, uri: Url, text: String, version: i32) {
+        self.documents.insert(uri, Document::new(text, version));
+    }
+
+    /// Applies the changes the editor made to a document, parsing it again after each one,
+    /// and shifting the spans of what they moved once they're all applied.
+    pub fn change(
+        &mut self,
+        uri: &Url,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) {
+        let Some(document) = self.documents.get_mut(uri) else {
+            return;
+        };
+
+        let mut shifts = PendingShifts::default();
+        for change in changes {
+            document.apply(change, &mut shifts);
+        }
+        shifts.apply(&mut document.file);
+        document.version = version;
+    }
+
+    /// Stops tracking the editor's buffer, going back to what is on disk,
+    /// or forgetting the file if it isn't in the workspace folders.
+    pub fn close(&mut self, uri: &Url) {
+        self.documents.remove(uri);
+
+        match self.is_indexed(uri) {
+            true => self.read(uri.clone()),
+            false => {
+                self.on_disk.remove(uri);
+            }
+        }
+    }
+
+    pub fn get(&self, uri: &Url) -> Option<&Document> {
+        self.documents.get(uri).or_else(|| self.on_disk.get(uri))
+    }
+
+    /// Every document in the workspace, preferring the editor's buffers over what is on disk.
+    pub fn documents(&self) -> impl Iterator<Item = (&Url, &Document)> {
+        let on_disk = self
+            .on_disk
+            .iter()
+            .filter(|(uri, _)| !self.documents.contains_key(*uri));
+
+        self.documents.iter().chain(on_disk)
+    }
+}
+
+/// Every `.nov` file in `folder` and its subfolders,
+/// leaving out hidden folders, build output and symlinks.
+pub fn scripts(folder: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return vec![];
+    };
+
+    let mut scripts = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() && !is_skipped(&entry.file_name()) {
+            scripts.extend(self::scripts(&path));
+        } else if file_type.is_file() && is_script(&path) {
+            scripts.push(path);
+        }
+    }
+
+    scripts
+}
+
+fn is_script(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "nov")
+}
+
+/// Whether a folder is hidden or has build output, and so has no scripts worth reading.
+fn is_skipped(folder: &OsStr) -> bool {
+    folder
+        .to_str()
+        .is_some_and(|folder| folder.starts_with('.') || folder == "target")
+}
+
+/// An open document, along with what was parsed from it.
+pub struct Document {
+    pub text: String,
+    pub version: i32,
+    /// The syntax tree of the text, which `file` is derived from.
+    pub tree: Arc<GreenNode>,
+    pub file: File,
+    pub errors: Vec<RecoveredError>,
+    line_starts: LineStarts,
+}
+
+impl Document {
+    pub fn new(text: String, version: i32) -> Self {
+        let (tree, errors) = crate::parser::parse_tree_recovering(&text);
+        let line_starts = LineStarts::new(&text);
+
+        Document {
+            text,
+            version,
+            file: crate::syntax::lower(&SyntaxNode::new_root(tree.clone())),
+            tree,
+            errors,
+            line_starts,
+        }
+    }
+
+    pub fn syntax(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.tree.clone())
+    }
+
+    pub fn lines(&self) -> LineIndex<'_> {
+        self.line_starts.index(&self.text)
+    }
+
+    /// The byte offset of an editor position.
+    pub fn offset(&self, position: Position) -> usize {
+        self.lines().offset_utf16(LineColumn {
+            line: position.line as usize,
+            column: position.character as usize,
+        })
+    }
+
+    /// Applies a change, only parsing again the scenes it touched if it has a range.
+    ///
+    /// The spans of the scenes after it are left for `shifts` to shift.
+    fn apply(&mut self, change: TextDocumentContentChangeEvent, shifts: &mut PendingShifts) {
+        match change.range {
+            Some(range) => {
+                let start = self.offset(range.start);
+                let end = self.offset(range.end).max(start);
+                self.text.replace_range(start..end, &change.text);
+                self.line_starts.edit(start..end, &change.text);
+
+                let edit = Edit {
+                    range: start..end,
+                    inserted: change.text.len(),
+                };
+                crate::parser::reparse_with(
+                    &self.text,
+                    &self.line_starts.index(&self.text),
+                    &edit,
+                    &mut self.tree,
+                    &mut self.file,
+                    &mut self.errors,
+                    shifts,
+                );
+            }
+            None => {
+                // The spans of a document parsed from scratch are where they should be already.
+                *shifts = PendingShifts::default();
+                *self = Document::new(change.text, self.version);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closed_files_are_only_kept_if_they_are_in_the_workspace() {
+        let root = std::env::temp_dir().join(format!("novel-close-{}", std::process::id()));
+        let folder = root.join("story");
+        std::fs::create_dir_all(&folder).unwrap();
+        let inside = folder.join("inside.nov");
+        let outside = root.join("outside.nov");
+        for script in [&inside, &outside] {
+            std::fs::write(script, "TITLE\n\n====\n\nHi.\n").unwrap();
+        }
+
+        let mut workspace = Workspace::default();
+        workspace.add_folder(folder.clone());
+        for script in [&inside, &outside] {
+            let uri = Url::from_file_path(script).unwrap();
+            workspace.open(uri.clone(), "TITLE\n".to_owned(), 1);
+            workspace.close(&uri);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let uris: Vec<_> = workspace.documents().map(|(uri, _)| uri.clone()).collect();
+        assert_eq!(uris, [Url::from_file_path(&inside).unwrap()]);
+    }
+
+    #[test]
+    fn scripts_leave_out_hidden_folders_build_output_and_symlinks() {
+        let folder = std::env::temp_dir().join(format!("novel-scripts-{}", std::process::id()));
+        for subfolder in ["story", ".git", "target"] {
+            std::fs::create_dir_all(folder.join(subfolder)).unwrap();
+        }
+        for script in [
+            "main.nov",
+            "story/one.nov",
+            ".git/two.nov",
+            "target/three.nov",
+        ] {
+            std::fs::write(folder.join(script), "TITLE\n").unwrap();
+        }
+        std::fs::write(folder.join("notes.txt"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(folder.join("main.nov"), folder.join("link.nov")).unwrap();
+
+        let mut scripts: Vec<_> = scripts(&folder)
+            .into_iter()
+            .map(|script| script.strip_prefix(&folder).unwrap().to_owned())
+            .collect();
+        scripts.sort();
+        std::fs::remove_dir_all(&folder).unwrap();
+
+        assert_eq!(scripts, [Path::new("main.nov"), Path::new("story/one.nov")]);
+    }
+}
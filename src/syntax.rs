@@ -0,0 +1,455 @@
+//! The concrete syntax tree of a novel file, which keeps every character of its source.
+//!
+//! Like in rowan, the tree comes in two layers. Green nodes are immutable and only know
+//! their kind, their length and their children, so they can be shared between versions
+//! of a tree. Red nodes ([`SyntaxNode`]) are built on top of them as the tree is walked,
+//! and know where in the source they are and what their parent is.
+//!
+//! The [`File`](crate::types::File) the rest of the crate works with is derived from this tree by [`lower`].
+
+mod lower;
+
+pub use lower::{lower, lower_scene};
+
+use crate::types::Span;
+use std::{rc::Rc, sync::Arc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    // Tokens.
+    /// Spaces, tabs and line breaks that separate things.
+    Whitespace,
+    /// A line break that ends a line of something.
+    Newline,
+    /// `==`, around scene names.
+    Equals2,
+    /// `=`, between a variable and its value.
+    Equals,
+    Colon,
+    Comma,
+    Pipe,
+    At,
+    Hash,
+    /// `>`, which starts a tagged directive like `> SFX: text`.
+    Gt,
+    /// `-`, before a line of dialogue.
+    Dash,
+    /// `//`, which starts a spoiler.
+    Slash2,
+    /// `---`, around frontmatter.
+    Fence,
+    LBracket,
+    RBracket,
+    /// `[[`, which opens a reference in text.
+    LBracket2,
+    RBracket2,
+    LBrace,
+    RBrace,
+    /// `CONT`, `IF` or `SET` after an `@`.
+    Keyword,
+    /// A metadata key or the tag of a tagged action.
+    Ident,
+    /// The name of a scene or of a variable.
+    Name,
+    Referent,
+    Alias,
+    Text,
+    Number,
+    /// `true` or `false`.
+    Boolean,
+    Date,
+    Time,
+    Duration,
+    /// A backslash and the character it escapes.
+    Escape,
+    /// The markers around a formatted section, like `**`.
+    Delimiter,
+    /// Source that couldn't be parsed.
+    Error,
+
+    // Nodes.
+    File,
+    /// Metadata entries between `---` fences, at the start of a file.
+    Frontmatter,
+    /// The title of a file and maybe its subtitle, each on a line of its own.
+    TitleBlock,
+    /// Metadata entries, of a file or a scene.
+    Meta,
+    MetaEntry,
+    /// A value, which is a list when it has other values in it.
+    Value,
+    Scene,
+    SceneHeader,
+    ActionBlock,
+    DialogueBlock,
+    SpoilerBlock,
+    TaggedAction,
+    Cont,
+    If,
+    /// The `{ ... }` block of an `@IF`.
+    Block,
+    Set,
+    RichText,
+    /// A reference in text, with its brackets.
+    Link,
+    Formatted,
+    /// `{Name}`, in text.
+    Variable,
+    Reference,
+}
+
+impl SyntaxKind {
+    /// Whether nodes of this kind are items of a scene.
+    pub fn is_item(self) -> bool {
+        use SyntaxKind::*;
+        matches!(
+            self,
+            ActionBlock | DialogueBlock | SpoilerBlock | TaggedAction | Cont | If | Set
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct GreenToken {
+    kind: SyntaxKind,
+    text: String,
+}
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: &str) -> Self {
+        GreenToken {
+            kind,
+            text: text.to_owned(),
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    len: usize,
+    children: Vec<GreenElement>,
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::len).sum();
+        GreenNode {
+            kind,
+            len,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    /// The length of the node's text, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+
+    /// The source the node was parsed from.
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.len);
+        self.write_text(&mut text);
+        text
+    }
+
+    fn write_text(&self, text: &mut String) {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => node.write_text(text),
+                GreenElement::Token(token) => text.push_str(&token.text),
+            }
+        }
+    }
+
+    /// A copy of the node with the children in `range` replaced.
+    pub fn splice_children(
+        &self,
+        range: std::ops::Range<usize>,
+        replacement: impl IntoIterator<Item = GreenElement>,
+    ) -> GreenNode {
+        let mut children = self.children.clone();
+        children.splice(range, replacement);
+        GreenNode::new(self.kind, children)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(Arc<GreenNode>),
+    Token(Arc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn node(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        GreenElement::Node(Arc::new(GreenNode::new(kind, children)))
+    }
+
+    pub fn token(kind: SyntaxKind, text: &str) -> Self {
+        GreenElement::Token(Arc::new(GreenToken::new(kind, text)))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            GreenElement::Node(node) => node.kind,
+            GreenElement::Token(token) => token.kind,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A node of the tree, at a particular place in it.
+///
+/// Cloning one is cheap, and so is walking up or down the tree from it.
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Arc<GreenNode>,
+    parent: Option<SyntaxNode>,
+    /// Where the node is among its parent's children.
+    index: usize,
+    offset: usize,
+}
+
+impl PartialEq for SyntaxNode {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0.green, &other.0.green) && self.0.offset == other.0.offset
+    }
+}
+
+impl Eq for SyntaxNode {}
+
+impl SyntaxNode {
+    pub fn new_root(green: Arc<GreenNode>) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            parent: None,
+            index: 0,
+            offset: 0,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Arc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    /// Where the node is among its parent's children, counting tokens.
+    pub fn index(&self) -> usize {
+        self.0.index
+    }
+
+    pub fn span(&self) -> Span {
+        self.0.offset..self.0.offset + self.0.green.len
+    }
+
+    pub fn text(&self) -> String {
+        self.0.green.text()
+    }
+
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+
+        self.0
+            .green
+            .children
+            .iter()
+            .enumerate()
+            .map(move |(index, child)| {
+                let start = offset;
+                offset += child.len();
+
+                match child {
+                    GreenElement::Node(green) => {
+                        SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                            green: green.clone(),
+                            parent: Some(self.clone()),
+                            index,
+                            offset: start,
+                        })))
+                    }
+                    GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                        green: green.clone(),
+                        parent: self.clone(),
+                        index,
+                        offset: start,
+                    }),
+                }
+            })
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens()
+            .filter_map(SyntaxElement::into_node)
+    }
+
+    /// The tokens directly in this node, not the ones in its children.
+    pub fn tokens(&self) -> impl Iterator<Item = SyntaxToken> + '_ {
+        self.children_with_tokens()
+            .filter_map(SyntaxElement::into_token)
+    }
+
+    /// The first child node of the given kind.
+    pub fn child(&self, kind: SyntaxKind) -> Option<SyntaxNode> {
+        self.children().find(|child| child.kind() == kind)
+    }
+
+    /// The first token of the given kind directly in this node.
+    pub fn token(&self, kind: SyntaxKind) -> Option<SyntaxToken> {
+        self.tokens().find(|token| token.kind() == kind)
+    }
+
+    /// This node and every node in it, in source order.
+    pub fn descendants(&self) -> Vec<SyntaxNode> {
+        let mut descendants = vec![self.clone()];
+        for child in self.children() {
+            descendants.extend(child.descendants());
+        }
+        descendants
+    }
+
+    /// The token that `offset` is in, preferring the one that starts there.
+    pub fn token_at_offset(&self, offset: usize) -> Option<SyntaxToken> {
+        let element = self
+            .children_with_tokens()
+            .find(|element| element.span().contains(&offset))?;
+
+        match element {
+            SyntaxElement::Node(node) => node.token_at_offset(offset),
+            SyntaxElement::Token(token) => Some(token),
+        }
+    }
+
+    /// The smallest node that contains all of `span`.
+    pub fn covering_node(&self, span: Span) -> SyntaxNode {
+        let child = self.children().find(|child| {
+            let range = child.span();
+            range.start <= span.start && span.end <= range.end
+        });
+
+        match child {
+            Some(child) => child.covering_node(span),
+            None => self.clone(),
+        }
+    }
+
+    /// The green tree of the whole file, with this node replaced by `replacement`.
+    ///
+    /// Everything but the ancestors of the node is shared with the original tree.
+    pub fn replace_with(&self, replacement: GreenNode) -> Arc<GreenNode> {
+        let replacement = Arc::new(replacement);
+
+        match self.parent() {
+            Some(parent) => parent.replace_with(parent.green().splice_children(
+                self.index()..self.index() + 1,
+                [GreenElement::Node(replacement)],
+            )),
+            None => replacement,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Arc<GreenToken>,
+    parent: SyntaxNode,
+    index: usize,
+    offset: usize,
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn span(&self) -> Span {
+        self.offset..self.offset + self.green.text.len()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            SyntaxElement::Node(node) => node.kind(),
+            SyntaxElement::Token(token) => token.kind(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxElement::Node(node) => node.span(),
+            SyntaxElement::Token(token) => token.span(),
+        }
+    }
+
+    pub fn into_node(self) -> Option<SyntaxNode> {
+        match self {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        }
+    }
+
+    pub fn into_token(self) -> Option<SyntaxToken> {
+        match self {
+            SyntaxElement::Token(token) => Some(token),
+            SyntaxElement::Node(_) => None,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use super::{SyntaxElement, SyntaxKind, SyntaxNode};
+use crate::types::*;
+
+/// Derives the abstract syntax tree of a file from its concrete one.
+///
+/// Whatever couldn't be parsed, and so is left as [`SyntaxKind::Error`] in the tree, is left out.
+pub fn lower(root: &SyntaxNode) -> File {
+    File {
+        frontmatter: Some(frontmatter(root)),
+        scenes: root
+            .children()
+            .filter(|child| child.kind() == SyntaxKind::Scene)
+            .map(|node| Spanned::new(lower_scene(&node), node.span()))
+            .collect(),
+    }
+}
+
+/// The metadata of a file, from between its `---` fences, after them, and its title block,
+/// whose lines are its `Title` and `Subtitle` unless those are written out.
+/// `T` and `S` are short for those.
+fn frontmatter(root: &SyntaxNode) -> Metadata {
+    let mut frontmatter = match root.child(SyntaxKind::Frontmatter) {
+        Some(fenced) => meta(&fenced),
+        None => Metadata::default(),
+    };
+    frontmatter.extend(meta(root));
+
+    let mut frontmatter: Metadata = frontmatter
+        .into_iter()
+        .map(|(key, value)| {
+            let key = key.map(|key| match key.as_str() {
+                "T" => "Title".to_owned(),
+                "S" => "Subtitle".to_owned(),
+                _ => key,
+            });
+            (key, value)
+        })
+        .collect();
+
+    if let Some(title_block) = root.child(SyntaxKind::TitleBlock) {
+        // There's no key written in the source, so they span nothing, where the title block starts.
+        let start = title_block.span().start;
+
+        for (key, value) in ["Title", "Subtitle"]
+            .into_iter()
+            .zip(title_block.children())
+        {
+            // A `Title:` or `Subtitle:` written out is what was meant, over the title block.
+            frontmatter
+                .entry(Spanned::new(key.to_owned(), start..start))
+                .or_insert_with(|| Spanned::new(self::value(&value), value.span()));
+        }
+    }
+
+    frontmatter
+}
+
+/// The metadata entries of a file or a scene, which are in its `Meta` child.
+fn meta(parent: &SyntaxNode) -> Metadata {
+    let Some(meta) = parent.child(SyntaxKind::Meta) else {
+        return Metadata::default();
+    };
+
+    meta.children()
+        .map(|entry| {
+            let key = entry.token(SyntaxKind::Ident).unwrap();
+            let value = entry.child(SyntaxKind::Value).unwrap();
+            (
+                Spanned::new(key.text().to_owned(), key.span()),
+                Spanned::new(self::value(&value), value.span()),
+            )
+        })
+        .collect()
+}
+
+fn value(node: &SyntaxNode) -> Value {
+    let items: Vec<_> = node
+        .children()
+        .filter(|child| child.kind() == SyntaxKind::Value)
+        .map(|item| Spanned::new(value(&item), item.span()))
+        .collect();
+    if !items.is_empty() {
+        return Value::List(items);
+    }
+
+    if let Some(rich_text) = node.child(SyntaxKind::RichText) {
+        return Value::RichText(self::rich_text(&rich_text));
+    }
+    if let Some(reference) = node.child(SyntaxKind::Reference) {
+        return Value::Reference(self::reference(&reference));
+    }
+
+    let token = node.tokens().next().unwrap();
+    let text = token.text();
+    match token.kind() {
+        SyntaxKind::Number => Value::Number(Typed::new(text.parse().unwrap(), text)),
+        SyntaxKind::Boolean => Value::Boolean(text == "true"),
+        SyntaxKind::Date => Value::Date(Typed::new(Date::parse(text).unwrap(), text)),
+        SyntaxKind::Time => Value::Time(Typed::new(Time::parse(text).unwrap(), text)),
+        SyntaxKind::Duration => Value::Duration(Typed::new(Duration::parse(text).unwrap(), text)),
+        kind => unreachable!("{kind:?} is not a value"),
+    }
+}
+
+/// Derives a scene from its node.
+pub fn lower_scene(node: &SyntaxNode) -> Scene {
+    // Items before the first scene header are a scene without one.
+    let header = node.child(SyntaxKind::SceneHeader);
+    let name = header.and_then(|header| header.token(SyntaxKind::Name));
+
+    Scene {
+        name: name.map(|name| Spanned::new(name.text().to_owned(), name.span())),
+        meta: meta(node),
+        items: items(node),
+    }
+}
+
+/// The items directly in a scene or a block.
+fn items(node: &SyntaxNode) -> Vec<Spanned<SceneItem>> {
+    node.children()
+        .filter(|child| child.kind().is_item())
+        .map(|child| Spanned::new(item(&child), child.span()))
+        .collect()
+}
+
+fn item(node: &SyntaxNode) -> SceneItem {
+    let rich_text = || rich_text(&node.child(SyntaxKind::RichText).unwrap());
+
+    match node.kind() {
+        SyntaxKind::ActionBlock => {
+            let mut parts = vec![];
+            for child in node.children_with_tokens() {
+                match child {
+                    SyntaxElement::Node(line) => parts.extend(self::rich_text(&line).0),
+                    SyntaxElement::Token(line_break) => parts.push(Spanned::new(
+                        RichTextPart::Text("\n".to_owned()),
+                        line_break.span(),
+                    )),
+                }
+            }
+
+            SceneItem::ActionBlock(RichText(merge_text_parts(parts)))
+        }
+        SyntaxKind::DialogueBlock => SceneItem::DialogueBlock {
+            // Without a speaker, the lines continue what the last speaker said, as if it were `[&]`.
+            speaker: match node.child(SyntaxKind::Reference) {
+                Some(speaker) => reference(&speaker),
+                None => {
+                    let start = node.span().start;
+                    let reference = Reference {
+                        referent: "&".to_owned(),
+                        alias: None,
+                    };
+                    Spanned::new(reference, start..start)
+                }
+            },
+            block: node
+                .children()
+                .filter(|child| child.kind() == SyntaxKind::RichText)
+                .map(|line| self::rich_text(&line))
+                .collect(),
+        },
+        SyntaxKind::SpoilerBlock => SceneItem::SpoilerBlock(rich_text()),
+        SyntaxKind::TaggedAction => {
+            let tag = node.token(SyntaxKind::Ident).unwrap();
+            SceneItem::TaggedAction(Spanned::new(tag.text().to_owned(), tag.span()), rich_text())
+        }
+        SyntaxKind::Cont => {
+            let target = reference(&node.child(SyntaxKind::Reference).unwrap());
+            SceneItem::Cont(target.map(|target| Reference {
+                referent: target.referent.trim().to_owned(),
+                alias: target.alias.map(|alias| alias.trim().to_owned()),
+            }))
+        }
+        SyntaxKind::If => {
+            let items = match node.child(SyntaxKind::Block) {
+                Some(block) => items(&block),
+                None => items(node),
+            };
+
+            SceneItem::If(rich_text(), items)
+        }
+        SyntaxKind::Set => {
+            let name = node.token(SyntaxKind::Name).unwrap();
+            let value = node
+                .child(SyntaxKind::Value)
+                .map(|value| Spanned::new(self::value(&value), value.span()));
+
+            SceneItem::Set(Spanned::new(name.text().to_owned(), name.span()), value)
+        }
+        kind => unreachable!("{kind:?} is not a scene item"),
+    }
+}
+
+fn rich_text(node: &SyntaxNode) -> RichText {
+    let parts = node
+        .children_with_tokens()
+        .map(|child| {
+            let part = match &child {
+                SyntaxElement::Token(token) if token.kind() == SyntaxKind::Escape => {
+                    RichTextPart::Text(token.text()[1..].to_owned())
+                }
+                SyntaxElement::Token(token) => RichTextPart::Text(token.text().to_owned()),
+                SyntaxElement::Node(link) if link.kind() == SyntaxKind::Link => {
+                    RichTextPart::Reference(reference(&link.child(SyntaxKind::Reference).unwrap()))
+                }
+                SyntaxElement::Node(variable) if variable.kind() == SyntaxKind::Variable => {
+                    let name = variable.token(SyntaxKind::Name).unwrap();
+                    RichTextPart::Variable(name.text().to_owned())
+                }
+                SyntaxElement::Node(section) => {
+                    let delimiter = section.token(SyntaxKind::Delimiter).unwrap();
+                    let content = self::rich_text(&section.child(SyntaxKind::RichText).unwrap());
+                    RichTextPart::FormattedSection(formatting_tag(delimiter.text()), content)
+                }
+            };
+
+            Spanned::new(part, child.span())
+        })
+        .collect();
+
+    RichText(merge_text_parts(parts))
+}
+
+fn formatting_tag(delimiter: &str) -> FormattingTag {
+    match delimiter {
+        "***" => FormattingTag::BoldItalic,
+        "**" => FormattingTag::Bold,
+        "*" | "_" => FormattingTag::Italic,
+        "__" => FormattingTag::Underline,
+        "~~" => FormattingTag::Strikethrough,
+        // `[name]`, with the name of the tag in it.
+        other => FormattingTag::Other(other[1..other.len() - 1].to_owned()),
+    }
+}
+
+/// Joins adjacent [`RichTextPart::Text`]s, which stray markers, escapes and line breaks leave behind.
+fn merge_text_parts(parts: Vec<Spanned<RichTextPart>>) -> Vec<Spanned<RichTextPart>> {
+    let mut merged: Vec<Spanned<RichTextPart>> = Vec::with_capacity(parts.len());
+
+    for part in parts {
+        if let RichTextPart::Text(text) = &part.node
+            && let Some(previous) = merged.last_mut()
+            && let RichTextPart::Text(previous_text) = &mut previous.node
+        {
+            previous_text.push_str(text);
+            previous.span.end = part.span.end;
+            continue;
+        }
+
+        merged.push(part);
+    }
+
+    merged
+}
+
+fn reference(node: &SyntaxNode) -> Spanned<Reference> {
+    let referent = node.token(SyntaxKind::Referent).unwrap();
+    let alias = node.token(SyntaxKind::Alias);
+
+    let reference = Reference {
+        referent: referent.text().to_owned(),
+        alias: alias.map(|alias| alias.text().to_owned()),
+    };
+
+    Spanned::new(reference, node.span())
+}
@@ -1,53 +1,400 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    ops::{Deref, DerefMut, Range},
+};
 
 pub type Identifier = String;
 pub type TimelinePoint = String;
 
+/// A byte range of a source file.
+pub type Span = Range<usize>;
+
+/// A node of the syntax tree, along with the span of source it was parsed from.
+///
+/// Spans are ignored when comparing nodes, so that the same script
+/// compares equal however it is laid out in its source.
+#[derive(Debug, Clone, Default)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Spanned { node, span }
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        Spanned {
+            node: f(self.node),
+            span: self.span,
+        }
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.node
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.node
+    }
+}
+
+impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl<T: Hash> Hash for Spanned<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.node.hash(state);
+    }
+}
+
+/// Lets metadata be looked up by key, as in `scene.meta.get("Summary")`.
+impl Borrow<str> for Spanned<String> {
+    fn borrow(&self) -> &str {
+        &self.node
+    }
+}
+
+/// Key-value pairs describing a file or a scene, in the order they were written.
+pub type Metadata = IndexMap<Spanned<Identifier>, Spanned<Value>>;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct File {
-    pub frontmatter: Option<HashMap<String, Value>>,
-    pub scenes: Vec<Scene>,
+    pub frontmatter: Option<Metadata>,
+    pub scenes: Vec<Spanned<Scene>>,
 }
 
+/// The value of a metadata entry or a variable.
+///
+/// Values are typed when the whole of them is one thing that can be typed, like `2029-06-21`,
+/// or a comma-separated list of them, like `[Claire], [Andy]`. Otherwise, they're rich text.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     RichText(RichText),
-    List(Vec<Value>),
+    List(Vec<Spanned<Value>>),
+    Number(Typed<f64>),
+    Boolean(bool),
+    Date(Typed<Date>),
+    Time(Typed<Time>),
+    Duration(Typed<Duration>),
+    /// A reference to something by name, like `[Claire]`.
+    Reference(Spanned<Reference>),
 }
 
 impl Value {
+    pub fn rich_texts(&self) -> Vec<&RichText> {
+        match self {
+            Value::RichText(rich_text) => vec![rich_text],
+            Value::List(values) => values.iter().flat_map(|value| value.rich_texts()).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Every reference in the value, whether it is one or it's in its rich text.
+    pub fn references(&self) -> Vec<&Spanned<Reference>> {
+        match self {
+            Value::RichText(rich_text) => rich_text.references(),
+            Value::List(values) => values.iter().flat_map(|value| value.references()).collect(),
+            Value::Reference(reference) => vec![reference],
+            _ => vec![],
+        }
+    }
+
     pub fn as_string(&self) -> String {
         match self {
             Value::RichText(rich_text) => rich_text.as_string(),
             Value::List(values) => values
                 .iter()
                 .map(|value| value.as_string())
-                .reduce(|a, b| format!("{a}{b}"))
-                .unwrap_or(String::new()),
+                .collect::<Vec<_>>()
+                .join(", "),
+            Value::Number(number) => number.to_string(),
+            Value::Boolean(boolean) => boolean.to_string(),
+            Value::Date(date) => date.to_string(),
+            Value::Time(time) => time.to_string(),
+            Value::Duration(duration) => duration.to_string(),
+            Value::Reference(reference) => reference
+                .alias
+                .clone()
+                .unwrap_or_else(|| reference.referent.clone()),
+        }
+    }
+}
+
+/// A typed value, along with how it was written, so that it is written back the same way.
+///
+/// Displaying it gives what was written, like `1.50`, rather than the value, `1.5`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Typed<T> {
+    pub value: T,
+    pub text: String,
+}
+
+impl<T> Typed<T> {
+    pub fn new(value: T, text: impl Into<String>) -> Self {
+        Typed {
+            value,
+            text: text.into(),
         }
     }
 }
 
+impl<T> Deref for Typed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T> std::fmt::Display for Typed<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// A calendar date, like `2029-06-21`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// Reads a date written as `YYYY-MM-DD`.
+    pub fn parse(text: &str) -> Option<Date> {
+        let [year, month, day] = numbers(text, '-')?;
+        let date = Date {
+            year: year.parse().ok()?,
+            month: month.parse().ok()?,
+            day: day.parse().ok()?,
+        };
+
+        let is_valid = year.len() == 4
+            && month.len() == 2
+            && day.len() == 2
+            && (1..=12).contains(&date.month)
+            && (1..=date.days_in_month()).contains(&date.day);
+        is_valid.then_some(date)
+    }
+
+    /// How many days the month of the date has, in its year.
+    pub fn days_in_month(&self) -> u8 {
+        let is_leap_year = self.year.is_multiple_of(4)
+            && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400));
+
+        match self.month {
+            2 if is_leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+impl std::fmt::Display for Date {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+/// A time of day, like `12:00` or `23:59:30`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: Option<u8>,
+}
+
+impl Time {
+    /// Reads a time written as `HH:MM` or `HH:MM:SS`, where the hour may have just one digit.
+    pub fn parse(text: &str) -> Option<Time> {
+        let (hour, minute, second) = match numbers(text, ':') {
+            Some([hour, minute, second]) => (hour, minute, Some(second)),
+            None => {
+                let [hour, minute] = numbers(text, ':')?;
+                (hour, minute, None)
+            }
+        };
+
+        let time = Time {
+            hour: hour.parse().ok()?,
+            minute: minute.parse().ok()?,
+            second: second.map(str::parse).transpose().ok()?,
+        };
+
+        let is_valid = hour.len() <= 2
+            && minute.len() == 2
+            && second.is_none_or(|second| second.len() == 2)
+            && time.hour < 24
+            && time.minute < 60
+            && time.second.is_none_or(|second| second < 60);
+        is_valid.then_some(time)
+    }
+}
+
+impl std::fmt::Display for Time {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)?;
+        match self.second {
+            Some(second) => write!(f, ":{second:02}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A length of time, like `1h 30m`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Duration {
+    pub seconds: u64,
+}
+
+impl Duration {
+    /// Reads a duration written as hours, minutes and seconds, like `1h 30m` or `90s`.
+    pub fn parse(text: &str) -> Option<Duration> {
+        let mut seconds = 0u64;
+        let mut rest = text;
+
+        while !rest.is_empty() {
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let amount: u64 = rest[..digits].parse().ok()?;
+            let unit = match rest[digits..].chars().next()? {
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => return None,
+            };
+
+            seconds = seconds.checked_add(amount.checked_mul(unit)?)?;
+            rest = rest[digits + 1..].trim_start_matches(' ');
+        }
+
+        (!text.is_empty()).then_some(Duration { seconds })
+    }
+}
+
+impl std::fmt::Display for Duration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts = [
+            (self.seconds / 3600, 'h'),
+            (self.seconds / 60 % 60, 'm'),
+            (self.seconds % 60, 's'),
+        ];
+        let parts: Vec<_> = parts
+            .iter()
+            .filter(|(amount, _)| *amount > 0)
+            .map(|(amount, unit)| format!("{amount}{unit}"))
+            .collect();
+
+        match parts.is_empty() {
+            true => write!(f, "0s"),
+            false => write!(f, "{}", parts.join(" ")),
+        }
+    }
+}
+
+/// Splits `text` into `N` parts of digits separated by `separator`.
+fn numbers<const N: usize>(text: &str, separator: char) -> Option<[&str; N]> {
+    let parts: Vec<&str> = text.split(separator).collect();
+    let parts: [&str; N] = parts.try_into().ok()?;
+    parts
+        .iter()
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        .then_some(parts)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Scene {
-    pub name: Option<String>,
-    pub meta: HashMap<Identifier, Value>,
-    pub items: Vec<SceneItem>,
+    pub name: Option<Spanned<String>>,
+    pub meta: Metadata,
+    pub items: Vec<Spanned<SceneItem>>,
+}
+
+impl Scene {
+    /// Every item in the scene, including the ones nested in other items, in source order.
+    pub fn all_items(&self) -> Vec<&Spanned<SceneItem>> {
+        fn collect<'a>(items: &'a [Spanned<SceneItem>], all: &mut Vec<&'a Spanned<SceneItem>>) {
+            for item in items {
+                all.push(item);
+                collect(item.children(), all);
+            }
+        }
+
+        let mut all = vec![];
+        collect(&self.items, &mut all);
+        all
+    }
+
+    /// The name of the scene, or its position if it has none.
+    pub fn name_or_position(&self, idx: usize) -> String {
+        self.name
+            .as_deref()
+            .cloned()
+            .unwrap_or_else(|| format!("Scene {}", idx + 1))
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum SceneItem {
     ActionBlock(RichText),
     DialogueBlock {
-        speaker: Reference,
+        speaker: Spanned<Reference>,
         block: Vec<RichText>,
     },
     SpoilerBlock(RichText),
-    TaggedAction(String, RichText),
-    Cont(Reference),
-    If(RichText, Vec<SceneItem>),
+    TaggedAction(Spanned<String>, RichText),
+    Cont(Spanned<Reference>),
+    If(RichText, Vec<Spanned<SceneItem>>),
+    Set(Spanned<Identifier>, Option<Spanned<Value>>),
+}
+
+impl SceneItem {
+    /// The items nested inside this one, like the ones in an `@IF` block.
+    pub fn children(&self) -> &[Spanned<SceneItem>] {
+        match self {
+            SceneItem::If(_, items) => items,
+            _ => &[],
+        }
+    }
+
+    /// The rich text written directly in this item, not counting the items nested in it.
+    pub fn rich_texts(&self) -> Vec<&RichText> {
+        match self {
+            SceneItem::ActionBlock(rich_text)
+            | SceneItem::SpoilerBlock(rich_text)
+            | SceneItem::TaggedAction(_, rich_text)
+            | SceneItem::If(rich_text, _) => vec![rich_text],
+            SceneItem::DialogueBlock { block, .. } => block.iter().collect(),
+            SceneItem::Set(_, Some(value)) => value.rich_texts(),
+            SceneItem::Cont(_) | SceneItem::Set(_, None) => vec![],
+        }
+    }
 }
 
+/// A reference to something by name, like `[[Treehouse|tree-house]]`.
+///
+/// When spanned, the span covers the `referent|alias` text, without any brackets around it.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Reference {
     pub referent: String,
@@ -55,15 +402,34 @@ pub struct Reference {
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct RichText(pub Vec<RichTextPart>);
+pub struct RichText(pub Vec<Spanned<RichTextPart>>);
 
 impl RichText {
     pub fn merge(self, other: RichText) -> Self {
         RichText([self.0, other.0].concat())
     }
 
+    /// Every reference in the text, including the ones inside formatted sections.
+    pub fn references(&self) -> Vec<&Spanned<Reference>> {
+        self.0
+            .iter()
+            .flat_map(|part| match &part.node {
+                RichTextPart::Reference(reference) => vec![reference],
+                RichTextPart::FormattedSection(_, rich_text) => rich_text.references(),
+                RichTextPart::Text(_) | RichTextPart::Variable(_) => vec![],
+            })
+            .collect()
+    }
+
+    /// The span from the start of the first part to the end of the last one.
+    pub fn span(&self) -> Option<Span> {
+        let first = self.0.first()?;
+        let last = self.0.last()?;
+        Some(first.span.start..last.span.end)
+    }
+
     pub fn is_parenthetical(&self) -> bool {
-        if let Some(RichTextPart::Text(text)) = self.0.first()
+        if let Some(RichTextPart::Text(text)) = self.0.first().map(|part| &part.node)
             && text.starts_with("(")
         {
             return true;
@@ -75,7 +441,7 @@ impl RichText {
     pub fn as_string(&self) -> String {
         let mut result: String = String::new();
         for part in self.0.iter() {
-            match part {
+            match &part.node {
                 RichTextPart::Text(text) => result.push_str(text.as_str()),
                 RichTextPart::Reference(reference) => result.push_str(
                     &reference
@@ -86,6 +452,7 @@ impl RichText {
                 RichTextPart::FormattedSection(_, rich_text) => {
                     result.push_str(rich_text.as_string().as_str());
                 }
+                RichTextPart::Variable(name) => result.push_str(&format!("{{{name}}}")),
             }
         }
         result
@@ -95,8 +462,10 @@ impl RichText {
 #[derive(Debug, PartialEq, Clone)]
 pub enum RichTextPart {
     Text(String),
-    Reference(Reference),
+    Reference(Spanned<Reference>),
     FormattedSection(FormattingTag, RichText),
+    /// `{Name}`, which stands for the value of a variable, or for a pronoun like `{they}`.
+    Variable(String),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -106,5 +475,6 @@ pub enum FormattingTag {
     BoldItalic,
     Underline,
     Strikethrough,
+    /// `[name]text[/name]`, with the name of the tag.
     Other(String),
 }
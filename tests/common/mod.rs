@@ -0,0 +1,34 @@
+//! What the tests share.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Every script in `examples/`, in a stable order, with its source.
+pub static CORPUS: LazyLock<Vec<(PathBuf, String)>> = LazyLock::new(|| {
+    fn collect(folder: &Path, files: &mut Vec<(PathBuf, String)>) {
+        let mut entries: Vec<_> = std::fs::read_dir(folder)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+
+        for path in entries {
+            if path.is_dir() {
+                collect(&path, files);
+            } else if path.extension().is_some_and(|extension| extension == "nov") {
+                let source = std::fs::read_to_string(&path).unwrap();
+                files.push((path, source));
+            }
+        }
+    }
+
+    let mut files = vec![];
+    collect(
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/examples")),
+        &mut files,
+    );
+    assert!(!files.is_empty());
+    files
+});
@@ -0,0 +1,192 @@
+//! What is reported about scripts that can't be parsed.
+
+use novel_lang::{
+    diagnostic::LineColumn,
+    parser::{parse, parse_recovering},
+    types::SceneItem,
+};
+
+const UNCLOSED_LINK: &str = "\
+TITLE
+
+== Start ==
+
+Hello [[Claire
+";
+
+#[test]
+fn errors_point_at_their_line_and_column() {
+    let diagnostic = parse(UNCLOSED_LINK).unwrap_err();
+
+    assert_eq!(
+        diagnostic.start,
+        LineColumn {
+            line: 4,
+            column: 14
+        }
+    );
+    assert_eq!(diagnostic.message(), "expected `]]`, found end of line");
+    assert_eq!(
+        diagnostic.to_string(),
+        "5:15: expected `]]`, found end of line"
+    );
+}
+
+#[test]
+fn errors_are_rendered_with_the_code_around_them() {
+    let diagnostic = parse(UNCLOSED_LINK).unwrap_err();
+
+    assert_eq!(
+        diagnostic.render(UNCLOSED_LINK, "start.nov"),
+        "\
+error: expected `]]`, found end of line
+ --> start.nov:5:15
+  |
+5 | Hello [[Claire
+  |               ^
+"
+    );
+}
+
+#[test]
+fn rendered_errors_underline_what_was_found() {
+    let source = "T\n\n== Start ==\n\n@GOTO End\n";
+    let diagnostic = parse(source).unwrap_err();
+
+    assert_eq!(
+        diagnostic.render(source, "start.nov"),
+        "\
+error: expected `CONT`, `IF` or `SET`, found `GOTO`
+ --> start.nov:5:2
+  |
+5 | @GOTO End
+  |  ^^^^
+"
+    );
+}
+
+#[test]
+fn long_line_numbers_widen_the_gutter() {
+    let source = format!("TITLE\n\n====\n{}\nHello [[Claire\n", "\n".repeat(100));
+    let diagnostic = parse(&source).unwrap_err();
+
+    assert_eq!(
+        diagnostic.render(&source, "long.nov"),
+        "\
+error: expected `]]`, found end of line
+   --> long.nov:105:15
+    |
+105 | Hello [[Claire
+    |               ^
+"
+    );
+}
+
+const TWO_SCENES: &str = "\
+T
+
+== A ==
+
+Hello [[Claire
+
+== B ==
+
+Bye.
+";
+
+#[test]
+fn recovery_skips_to_the_next_scene() {
+    let (file, errors) = parse_recovering(TWO_SCENES);
+
+    let [error] = errors.as_slice() else {
+        panic!("{} errors", errors.len());
+    };
+    assert_eq!(
+        error.diagnostic.message(),
+        "expected `]]`, found end of line"
+    );
+    assert_eq!(&TWO_SCENES[error.skipped.clone()], "[[Claire\n");
+
+    let names: Vec<_> = file
+        .scenes
+        .iter()
+        .map(|scene| scene.name.as_ref().map(|name| name.node.as_str()))
+        .collect();
+    assert_eq!(names, [Some("A"), Some("B")]);
+
+    let [item] = file.scenes[1].items.as_slice() else {
+        panic!("`B` has {} items", file.scenes[1].items.len());
+    };
+    let SceneItem::ActionBlock(action) = &item.node else {
+        panic!("`B` has {:?}", item.node);
+    };
+    assert_eq!(action.as_string(), "Bye.");
+}
+
+#[test]
+fn recovery_keeps_what_comes_before_an_error() {
+    let (file, _) = parse_recovering(TWO_SCENES);
+
+    let [item] = file.scenes[0].items.as_slice() else {
+        panic!("`A` has {} items", file.scenes[0].items.len());
+    };
+    let SceneItem::ActionBlock(action) = &item.node else {
+        panic!("`A` has {:?}", item.node);
+    };
+    assert_eq!(action.as_string(), "Hello ");
+}
+
+#[test]
+fn every_error_is_reported() {
+    let source = "\
+T
+
+== A ==
+
+Hello [[Claire
+
+Still here.
+
+Bye [[Andy
+";
+    let (file, errors) = parse_recovering(source);
+
+    let lines: Vec<_> = errors
+        .iter()
+        .map(|error| error.diagnostic.start.line + 1)
+        .collect();
+    assert_eq!(lines, [5, 9]);
+
+    let actions: Vec<_> = file.scenes[0]
+        .items
+        .iter()
+        .filter_map(|item| match &item.node {
+            SceneItem::ActionBlock(action) => Some(action.as_string()),
+            _ => None,
+        })
+        .collect();
+    assert!(actions.contains(&"Still here.".to_owned()), "{actions:?}");
+}
+
+#[test]
+fn recovered_errors_match_strict_ones() {
+    let source = TWO_SCENES.replace("Hello [[Claire", "@GOTO End");
+    let strict = parse(&source).unwrap_err();
+    let (file, errors) = parse_recovering(&source);
+
+    let [error] = errors.as_slice() else {
+        panic!("{} errors", errors.len());
+    };
+    assert_eq!(error.diagnostic, strict);
+    assert!(source[error.skipped.clone()].starts_with("@GOTO End"));
+    assert_eq!(file.scenes.len(), 2);
+}
+
+#[test]
+fn scripts_without_errors_recover_nothing() {
+    let source = "T\n\n== A ==\n\nHello [[Claire]]\n";
+    let (file, errors) = parse_recovering(source);
+
+    assert!(errors.is_empty());
+    assert_eq!(file, parse(source).unwrap());
+}
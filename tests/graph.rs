@@ -0,0 +1,193 @@
+//! The flow of a script from scene to scene.
+
+use common::CORPUS;
+use novel_lang::{
+    diagnostic::Severity,
+    graph::{EdgeKind, Graph},
+    parser::{parse, parse_recovering},
+};
+
+mod common;
+
+const SCRIPT: &str = "\
+BRANCHES
+
+== Start ==
+
+Where to?
+
+@IF Left @CONT Left
+@IF Right {
+    @CONT Right
+}
+@IF Up @CONT Sky
+
+== Left ==
+
+@CONT Start
+
+== Right ==
+
+@CONT Right
+
+== Lost ==
+
+Nobody ever gets here.
+
+== End ==
+
+It's over.
+";
+
+#[test]
+fn branches_become_edges() {
+    let file = parse(SCRIPT).unwrap();
+    let graph = Graph::new(&file);
+
+    let edges: Vec<_> = graph
+        .edges
+        .iter()
+        .map(|edge| {
+            let kind = match &edge.kind {
+                EdgeKind::FallThrough => "fall through".to_owned(),
+                EdgeKind::Cont(_) => "cont".to_owned(),
+                EdgeKind::If(conditions, _) => format!("if {}", conditions[0].as_string()),
+            };
+            (graph.name(edge.from), graph.name(edge.to), kind)
+        })
+        .collect();
+
+    let expected = [
+        ("Start", "Left", "if Left"),
+        ("Start", "Right", "if Right"),
+        ("Start", "Left", "fall through"),
+        ("Left", "Start", "cont"),
+        ("Right", "Right", "cont"),
+        ("Lost", "End", "fall through"),
+    ];
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|(from, to, kind)| (from.to_string(), to.to_string(), kind.to_string()))
+        .collect();
+    assert_eq!(edges, expected);
+
+    assert_eq!(graph.dangling.len(), 1);
+    assert_eq!(graph.dangling[0].1.referent, "Sky");
+}
+
+#[test]
+fn flow_problems_are_found() {
+    let file = parse(SCRIPT).unwrap();
+    let graph = Graph::new(&file);
+
+    assert_eq!(graph.unreachable(), vec![3, 4]);
+    assert_eq!(graph.dead_ends(), vec![4]);
+    assert_eq!(graph.cycles(), vec![vec![0, 1], vec![2]]);
+
+    let errors: Vec<_> = graph
+        .problems()
+        .into_iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .map(|problem| (problem.message, &SCRIPT[problem.span]))
+        .collect();
+    assert_eq!(
+        errors,
+        vec![("there is no scene called `Sky`".to_owned(), "Sky")]
+    );
+}
+
+#[test]
+fn corpus_continues_only_to_scenes_that_exist() {
+    for (path, source) in CORPUS.iter() {
+        // It continues to a `Key Scene` that it doesn't have, and `SCRIPT` covers that.
+        if path.ends_with("adventure.nov") {
+            continue;
+        }
+
+        let (file, _) = parse_recovering(source);
+        let graph = Graph::new(&file);
+        assert!(
+            graph.dangling.is_empty(),
+            "{} continues to scenes that don't exist",
+            path.display()
+        );
+    }
+}
+
+const ROUTES: &str = "\
+ROUTES
+
+== Home ==
+Where: [House]
+Summary: Claire \"wakes\" up.
+
+@IF the door is open @CONT Street
+
+== Street ==
+Where: [Town]
+
+@IF it rains @CONT Shelter
+
+@CONT Nowhere
+";
+
+#[test]
+fn graphs_are_drawn_in_dot() {
+    let file = parse(ROUTES).unwrap();
+
+    assert_eq!(
+        Graph::new(&file).to_dot(),
+        "\
+digraph {
+    node [shape=box, style=\"rounded,filled\", fillcolor=white];
+    scene0 [label=\"Home\\nClaire \\\"wakes\\\" up.\", fillcolor=\"#fde2e4\"];
+    scene1 [label=\"Street\", fillcolor=\"#e2ece9\"];
+    missing0 [label=\"Shelter\", style=dashed, color=red];
+    missing1 [label=\"Nowhere\", style=dashed, color=red];
+    scene0 -> scene1 [label=\"the door is open\"];
+    scene0 -> scene1 [style=dashed];
+    scene1 -> missing0 [label=\"it rains\"];
+    scene1 -> missing1;
+}
+"
+    );
+}
+
+#[test]
+fn graphs_are_drawn_in_mermaid() {
+    let file = parse(ROUTES).unwrap();
+
+    assert_eq!(
+        Graph::new(&file).to_mermaid(),
+        "\
+flowchart TD
+    scene0[\"Home<br/>Claire #quot;wakes#quot; up.\"]
+    scene1[\"Street\"]
+    missing0[\"Shelter\"]
+    missing1[\"Nowhere\"]
+    scene0 -->|\"the door is open\"| scene1
+    scene0 -.-> scene1
+    scene1 -->|\"it rains\"| missing0
+    scene1 --> missing1
+    style scene0 fill:#fde2e4
+    style scene1 fill:#e2ece9
+    style missing0 stroke:red,stroke-dasharray:5 5
+    style missing1 stroke:red,stroke-dasharray:5 5
+"
+    );
+}
+
+#[test]
+fn long_scripts_are_followed_without_recursing() {
+    let mut script = "LONG\n".to_owned();
+    let len = 60_000;
+    for idx in 0..len {
+        script.push_str(&format!("\n== {idx} ==\n\n@CONT {}\n", (idx + 1) % len));
+    }
+    let file = parse(&script).unwrap();
+    let graph = Graph::new(&file);
+
+    assert!(graph.unreachable().is_empty());
+    assert!(graph.dead_ends().is_empty());
+    assert_eq!(graph.cycles(), vec![(0..len).collect::<Vec<_>>()]);
+}
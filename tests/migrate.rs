@@ -0,0 +1,53 @@
+//! Rewriting a script in another dialect should only change how it is written.
+
+use common::CORPUS;
+use novel_lang::{
+    migrate::{Dialect, migrate},
+    parser::parse_recovering,
+};
+
+mod common;
+
+const DIALECTS: [Dialect; 2] = [Dialect::Bare, Dialect::Dashed];
+
+#[test]
+fn migrating_keeps_the_corpus_the_same() {
+    for (path, source) in CORPUS.iter() {
+        let (file, errors) = parse_recovering(source);
+
+        for dialect in DIALECTS {
+            let migrated = migrate(source, dialect);
+            let (migrated_file, migrated_errors) = parse_recovering(&migrated);
+            assert!(
+                migrated_file == file,
+                "{} changed in {dialect:?}:\n{migrated}",
+                path.display()
+            );
+            assert_eq!(migrated_errors.len(), errors.len());
+        }
+    }
+}
+
+#[test]
+fn migrating_again_changes_nothing() {
+    for (_, source) in CORPUS.iter() {
+        for dialect in DIALECTS {
+            let migrated = migrate(source, dialect);
+            assert_eq!(migrate(&migrated, dialect), migrated);
+        }
+    }
+}
+
+#[test]
+fn migrating_back_gives_the_same_source() {
+    let bare = ["inner_voices_pilot/pilot.nov", "test-adventure.nov"];
+
+    for (path, source) in CORPUS.iter() {
+        if bare.iter().any(|bare| path.ends_with(bare)) {
+            assert_eq!(&migrate(source, Dialect::Bare), source);
+
+            let dashed = migrate(source, Dialect::Dashed);
+            assert_eq!(&migrate(&dashed, Dialect::Bare), source);
+        }
+    }
+}
@@ -0,0 +1,309 @@
+//! What scripts parse into.
+
+use common::CORPUS;
+use novel_lang::{
+    parser::parse,
+    types::{Date, FormattingTag, RichText, RichTextPart, SceneItem, Typed, Value},
+};
+
+mod common;
+
+/// The items of the only scene in `body`, written under a scene header.
+fn items(body: &str) -> Vec<SceneItem> {
+    let source = format!("TITLE\n\n====\n\n{body}\n");
+    let mut file = parse(&source)
+        .unwrap_or_else(|diagnostic| panic!("{}", diagnostic.render(&source, "test")));
+    assert_eq!(file.scenes.len(), 1);
+    file.scenes
+        .remove(0)
+        .node
+        .items
+        .into_iter()
+        .map(|item| item.node)
+        .collect()
+}
+
+fn text(rich_text: &RichText) -> String {
+    rich_text.as_string()
+}
+
+#[test]
+fn cont_is_parsed_with_its_target() {
+    let parsed = items("@CONT Scene 02");
+    let [SceneItem::Cont(target)] = parsed.as_slice() else {
+        panic!("not a lone `@CONT`");
+    };
+    assert_eq!(target.referent, "Scene 02");
+    assert_eq!(target.alias, None);
+}
+
+#[test]
+fn inline_if_guards_one_directive() {
+    let parsed = items("@IF Apple @CONT Apple Scene");
+    let [SceneItem::If(condition, items)] = parsed.as_slice() else {
+        panic!("not a lone `@IF`");
+    };
+    assert_eq!(text(condition), "Apple");
+
+    let [item] = items.as_slice() else {
+        panic!("`@IF` guards {} items", items.len());
+    };
+    let SceneItem::Cont(target) = &item.node else {
+        panic!("`@IF` guards {:?}", item.node);
+    };
+    assert_eq!(target.referent, "Apple Scene");
+}
+
+#[test]
+fn block_if_guards_every_item_in_it() {
+    let body = "\
+@IF the player opens the chest {
+    A ball of light falls from the sky.
+
+    @SET Has Item
+    @CONT Treasure
+}
+
+The end.";
+
+    let parsed = items(body);
+    let [
+        SceneItem::If(condition, guarded),
+        SceneItem::ActionBlock(after),
+    ] = parsed.as_slice()
+    else {
+        panic!("not an `@IF` block followed by an action");
+    };
+    assert_eq!(text(condition), "the player opens the chest");
+    assert_eq!(text(after), "The end.");
+
+    let guarded: Vec<_> = guarded.iter().map(|item| &item.node).collect();
+    let [
+        SceneItem::ActionBlock(action),
+        SceneItem::Set(flag, None),
+        SceneItem::Cont(target),
+    ] = guarded.as_slice()
+    else {
+        panic!("`@IF` guards {guarded:?}");
+    };
+    assert_eq!(text(action), "A ball of light falls from the sky.");
+    assert_eq!(flag.node, "Has Item");
+    assert_eq!(target.referent, "Treasure");
+}
+
+#[test]
+fn set_raises_a_flag_or_gives_a_value() {
+    let parsed = items("@SET Has Apple\n@SET Apples = 3");
+    let [
+        SceneItem::Set(flag, None),
+        SceneItem::Set(variable, Some(value)),
+    ] = parsed.as_slice()
+    else {
+        panic!("not two `@SET`s");
+    };
+    assert_eq!(flag.node, "Has Apple");
+    assert_eq!(variable.node, "Apples");
+    assert_eq!(value.node, Value::Number(Typed::new(3.0, "3")));
+}
+
+#[test]
+fn directives_are_only_directives_at_the_start_of_a_line() {
+    let parsed = items("Mail me at someone@CONT.com");
+    let [SceneItem::ActionBlock(action)] = parsed.as_slice() else {
+        panic!("not a lone action");
+    };
+    assert!(matches!(
+        action.0.as_slice(),
+        [part] if part.node == RichTextPart::Text("Mail me at someone@CONT.com".to_owned())
+    ));
+}
+
+#[test]
+fn unclosed_formatting_markers_parse_quickly() {
+    let markers = ["*", "_", "~~", "**", "__", "***"];
+    let line: String = (0..50)
+        .map(|idx| format!("{}word ", markers[idx % markers.len()]))
+        .collect();
+
+    let start = std::time::Instant::now();
+    let parsed = items(line.trim_end());
+    assert!(
+        start.elapsed() < std::time::Duration::from_secs(1),
+        "took {:?}",
+        start.elapsed()
+    );
+
+    let [SceneItem::ActionBlock(action)] = parsed.as_slice() else {
+        panic!("not a lone action");
+    };
+    assert_eq!(text(action), line.trim_end());
+}
+
+const SPANNED: &str = "\
+TITLE
+
+== Scene 01 ==
+Where: [Hiere Forest]
+
+At the bed, [[Maple|Mapy]] is sleeping.
+
+[Casper]
+- Never...
+
+> SFX: Loud train horn.
+
+@SET Apples = 3
+@CONT Scene 02
+";
+
+#[test]
+fn spans_cover_what_was_written() {
+    let file = parse(SPANNED).unwrap();
+    let spanned = |span: std::ops::Range<usize>| &SPANNED[span];
+
+    let [scene] = file.scenes.as_slice() else {
+        panic!("{} scenes", file.scenes.len());
+    };
+    assert_eq!(
+        spanned(scene.name.as_ref().unwrap().span.clone()),
+        "Scene 01"
+    );
+
+    let (key, value) = scene.meta.first().unwrap();
+    assert_eq!(spanned(key.span.clone()), "Where");
+    assert_eq!(spanned(value.span.clone()), "[Hiere Forest]");
+
+    let items: Vec<_> = scene
+        .items
+        .iter()
+        .map(|item| spanned(item.span.clone()))
+        .collect();
+    assert_eq!(
+        items,
+        [
+            "At the bed, [[Maple|Mapy]] is sleeping.",
+            "[Casper]\n- Never...",
+            "> SFX: Loud train horn.",
+            "@SET Apples = 3",
+            "@CONT Scene 02",
+        ]
+    );
+
+    let [
+        SceneItem::ActionBlock(action),
+        SceneItem::DialogueBlock { speaker, .. },
+        SceneItem::TaggedAction(tag, _),
+        SceneItem::Set(variable, Some(value)),
+        SceneItem::Cont(target),
+    ] = scene
+        .items
+        .iter()
+        .map(|item| &item.node)
+        .collect::<Vec<_>>()
+        .as_slice()
+    else {
+        panic!("unexpected items in {:?}", scene.items);
+    };
+
+    let references = action.references();
+    let [reference] = references.as_slice() else {
+        panic!("not one reference in {action:?}");
+    };
+    assert_eq!(spanned(reference.span.clone()), "Maple|Mapy");
+    assert_eq!(spanned(speaker.span.clone()), "Casper");
+    assert_eq!(spanned(tag.span.clone()), "SFX");
+    assert_eq!(spanned(variable.span.clone()), "Apples");
+    assert_eq!(spanned(value.span.clone()), "3");
+    assert_eq!(spanned(target.span.clone()), "Scene 02");
+}
+
+#[test]
+fn t_and_s_are_short_for_title_and_subtitle() {
+    let file = parse("T: INNER VOICES 01\nS: The Mystery Kid Encounter\n\n====\n\nHi.\n").unwrap();
+    let frontmatter = file.frontmatter.unwrap();
+
+    let keys: Vec<_> = frontmatter.keys().map(|key| key.node.as_str()).collect();
+    assert_eq!(keys, ["Title", "Subtitle"]);
+    assert_eq!(frontmatter["Title"].as_string(), "INNER VOICES 01");
+    assert_eq!(
+        frontmatter["Subtitle"].as_string(),
+        "The Mystery Kid Encounter"
+    );
+}
+
+#[test]
+fn dates_only_have_the_days_their_month_has() {
+    for (date, is_valid) in [
+        ("2029-01-31", true),
+        ("2029-02-28", true),
+        ("2029-02-29", false),
+        ("2029-02-31", false),
+        ("2028-02-29", true),
+        ("2000-02-29", true),
+        ("1900-02-29", false),
+        ("2029-04-30", true),
+        ("2029-04-31", false),
+    ] {
+        assert_eq!(Date::parse(date).is_some(), is_valid, "{date}");
+    }
+
+    let parsed = items("@SET Deadline = 2029-02-31");
+    let [SceneItem::Set(_, Some(value))] = parsed.as_slice() else {
+        panic!("not a lone `@SET`");
+    };
+    assert!(matches!(value.node, Value::RichText(_)), "{:?}", value.node);
+}
+
+#[test]
+fn written_out_titles_win_over_the_title_block() {
+    let source = "---\nTitle: The Real Title\n---\nA Title Block\nIts Subtitle\n\n====\n\nHi.\n";
+    let file = parse(source).unwrap();
+    let frontmatter = file.frontmatter.unwrap();
+
+    assert_eq!(frontmatter["Title"].as_string(), "The Real Title");
+    assert_eq!(frontmatter["Subtitle"].as_string(), "Its Subtitle");
+
+    let (subtitle, _) = frontmatter.get_key_value("Subtitle").unwrap();
+    let block = source.find("A Title Block").unwrap();
+    assert_eq!(subtitle.span, block..block);
+}
+
+#[test]
+fn the_whole_corpus_parses() {
+    for (path, source) in CORPUS.iter() {
+        let name = path.to_string_lossy();
+        if let Err(diagnostic) = parse(source) {
+            panic!("{}", diagnostic.render(source, &name));
+        }
+    }
+}
+
+#[test]
+fn tags_are_formatting_of_their_own() {
+    let parsed = items("Go to [important]the *other* side[/important].");
+    let [SceneItem::ActionBlock(action)] = parsed.as_slice() else {
+        panic!("not a lone action");
+    };
+
+    let [_, section, _] = action.0.as_slice() else {
+        panic!("{action:?}");
+    };
+    let RichTextPart::FormattedSection(FormattingTag::Other(name), content) = &section.node else {
+        panic!("{section:?}");
+    };
+    assert_eq!(name, "important");
+    assert_eq!(text(content), "the other side");
+}
+
+#[test]
+fn items_before_the_first_scene_header_are_a_scene_of_their_own() {
+    let file = parse("TITLE\n\n#TRANS Slow fade\n\nA cabin.\n\n== Next ==\n\nHi.\n").unwrap();
+
+    let names: Vec<_> = file
+        .scenes
+        .iter()
+        .map(|scene| scene.name.as_ref().map(|name| name.node.as_str()))
+        .collect();
+    assert_eq!(names, [None, Some("Next")]);
+    assert_eq!(file.scenes[0].items.len(), 2);
+}
@@ -0,0 +1,88 @@
+//! Parsing a file again after an edit should give the same as parsing it from scratch.
+
+use common::CORPUS;
+use novel_lang::{
+    diagnostic::LineStarts,
+    parser::{Edit, PendingShifts, parse_recovering, parse_tree_recovering, reparse, reparse_with},
+};
+use proptest::{prelude::*, sample::Index};
+
+mod common;
+
+/// Text an edit may insert, full of what starts or ends scenes and items.
+const INSERTED: &str = r"(\n|\n\n|== ?|- |> |[a-zA-Z0-9 :,|@#{}*_~=\[\]\\]){0,12}";
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    /// A few edits, one after another, like someone typing would make.
+    #[test]
+    fn reparsing_matches_parsing(
+        file in any::<Index>(),
+        edits in prop::collection::vec((any::<Index>(), 0..40usize, INSERTED), 1..4),
+    ) {
+        let mut source = file.get(&CORPUS).1.clone();
+        let (mut tree, mut errors) = parse_tree_recovering(&source);
+        let mut parsed = parse_recovering(&source).0;
+
+        for (position, removed, inserted) in edits {
+            let (start, end) = range(&source, position, removed);
+            source.replace_range(start..end, &inserted);
+            let edit = Edit { range: start..end, inserted: inserted.len() };
+            reparse(&source, &edit, &mut tree, &mut parsed, &mut errors);
+
+            let (expected_tree, expected_errors) = parse_tree_recovering(&source);
+            let (expected, _) = parse_recovering(&source);
+            prop_assert!(tree == expected_tree, "trees differ for:\n{source}");
+            prop_assert_eq!(&errors, &expected_errors);
+            // Spans are ignored when comparing, so compare everything about the files.
+            prop_assert_eq!(format!("{parsed:?}"), format!("{expected:?}"));
+        }
+    }
+
+    /// Many edits in one go, like an editor sends, with the spans shifted once they're all made.
+    #[test]
+    fn reparsing_many_edits_at_once_matches_parsing(
+        file in any::<Index>(),
+        edits in prop::collection::vec((any::<Index>(), 0..40usize, INSERTED), 1..4),
+    ) {
+        let mut source = file.get(&CORPUS).1.clone();
+        let (mut tree, mut errors) = parse_tree_recovering(&source);
+        let mut parsed = parse_recovering(&source).0;
+        let mut line_starts = LineStarts::new(&source);
+        let mut shifts = PendingShifts::default();
+
+        for (position, removed, inserted) in edits {
+            let (start, end) = range(&source, position, removed);
+            source.replace_range(start..end, &inserted);
+            line_starts.edit(start..end, &inserted);
+            prop_assert_eq!(&line_starts, &LineStarts::new(&source));
+
+            let edit = Edit { range: start..end, inserted: inserted.len() };
+            let lines = line_starts.index(&source);
+            reparse_with(&source, &lines, &edit, &mut tree, &mut parsed, &mut errors, &mut shifts);
+        }
+        shifts.apply(&mut parsed);
+
+        let (expected, expected_errors) = parse_recovering(&source);
+        prop_assert_eq!(&errors, &expected_errors);
+        prop_assert_eq!(format!("{parsed:?}"), format!("{expected:?}"));
+    }
+}
+
+/// The range of an edit removing about `removed` bytes somewhere in `source`, on char boundaries.
+fn range(source: &str, position: Index, removed: usize) -> (usize, usize) {
+    let boundaries: Vec<usize> = source
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .chain([source.len()])
+        .collect();
+    let start = *position.get(&boundaries);
+    let end = boundaries
+        .iter()
+        .copied()
+        .find(|&idx| idx >= start + removed)
+        .unwrap_or(source.len());
+
+    (start, end)
+}
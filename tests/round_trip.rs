@@ -0,0 +1,296 @@
+//! Writing a file back as source and parsing that again should give back the same file,
+//! and the syntax tree of a source should give back that source exactly.
+
+use novel_lang::{
+    formatter::{emit, format},
+    migrate::{Dialect, migrate},
+    parser::{parse, parse_recovering, parse_tree_recovering},
+    types::{
+        Date, Duration, File, Metadata, Reference, RichText, RichTextPart, Scene, SceneItem,
+        Spanned, Time, Typed, Value,
+    },
+};
+use proptest::{prelude::*, sample::Index};
+use std::{path::Path, sync::LazyLock};
+
+mod common;
+
+/// Every script in `examples/`, with as much of it as could be parsed.
+static CORPUS: LazyLock<Vec<(&Path, &str, File)>> = LazyLock::new(|| {
+    common::CORPUS
+        .iter()
+        .map(|(path, source)| (path.as_path(), source.as_str(), parse_recovering(source).0))
+        .collect()
+});
+
+fn assert_round_trips(file: &File) {
+    let source = emit(file);
+    match parse(&source) {
+        Ok(parsed) => assert!(parsed == *file, "parsed differently:\n{source}"),
+        Err(diagnostic) => panic!("{}", diagnostic.render(&source, "emitted")),
+    }
+}
+
+#[test]
+fn corpus_round_trips() {
+    for (_, _, file) in CORPUS.iter() {
+        assert_round_trips(file);
+    }
+}
+
+#[test]
+fn emitting_again_changes_nothing() {
+    for (_, _, file) in CORPUS.iter() {
+        let source = emit(file);
+        assert_eq!(emit(&parse(&source).unwrap()), source);
+    }
+}
+
+#[test]
+fn formatting_again_changes_nothing() {
+    for (_, _, file) in CORPUS.iter() {
+        for dialect in [Dialect::Bare, Dialect::Dashed] {
+            let formatted = format(file, dialect);
+            assert_eq!(format(&parse(&formatted).unwrap(), dialect), formatted);
+        }
+    }
+}
+
+#[test]
+fn formatting_keeps_the_dialect() {
+    for (path, source, file) in CORPUS.iter() {
+        let dialect = Dialect::of(source);
+        let formatted = format(file, dialect);
+        assert_eq!(Dialect::of(&formatted), dialect, "{}", path.display());
+        assert_eq!(
+            migrate(&formatted, dialect),
+            formatted,
+            "{}",
+            path.display()
+        );
+    }
+
+    let dashed = "T\n\n====\n\n[Claire]\n- Hi.\n\n- Okay.\n\n>   SFX:  Door\n";
+    assert_eq!(
+        format(&parse(dashed).unwrap(), Dialect::of(dashed)),
+        "T\n\n====\n\n[Claire]\n- Hi.\n\n- Okay.\n\n> SFX: Door\n"
+    );
+}
+
+#[test]
+fn syntax_trees_keep_the_whole_corpus() {
+    for (path, source, _) in CORPUS.iter() {
+        let (tree, _) = parse_tree_recovering(source);
+        assert!(tree.text() == *source, "{} changed", path.display());
+    }
+}
+
+#[test]
+fn typed_values_are_written_as_they_were() {
+    let values = [
+        "1.50",
+        "99999999999999999999999",
+        "-0.0",
+        "2029-06-21",
+        "9:30",
+        "09:30:00",
+        "90m",
+        "1h 30m",
+        "3600s",
+        "true",
+    ];
+    let source: String = values
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| format!("Key{idx}: {value}\n"))
+        .collect();
+    let source = format!("TITLE\n\n====\n{source}\nHi.\n");
+
+    let file = parse(&source).unwrap();
+    let meta = &file.scenes[0].meta;
+    for (written, value) in values.iter().zip(meta.values()) {
+        assert!(
+            !matches!(value.node, Value::RichText(_)),
+            "`{written}` isn't typed"
+        );
+        assert_eq!(value.as_string(), *written);
+    }
+
+    assert_eq!(format(&file, Dialect::Bare), source);
+    assert_eq!(emit(&file), source);
+}
+
+#[test]
+fn typed_values_compare_by_how_they_were_written() {
+    let value = |source: &str| {
+        let file = parse(&format!("TITLE\n\n====\nKey: {source}\n\nHi.\n")).unwrap();
+        file.scenes[0].meta["Key"].node.clone()
+    };
+
+    assert_eq!(value("1.50"), value("1.50"));
+    assert_ne!(value("1.50"), value("1.5"));
+    let (Value::Number(wide), Value::Number(narrow)) = (value("1.50"), value("1.5")) else {
+        panic!("not numbers");
+    };
+    assert_eq!(wide.value, narrow.value);
+}
+
+fn text(text: &str) -> Spanned<RichTextPart> {
+    Spanned::new(RichTextPart::Text(text.to_owned()), 0..0)
+}
+
+fn scene(items: Vec<SceneItem>) -> Spanned<Scene> {
+    let scene = Scene {
+        name: Some(Spanned::new("Generated".to_owned(), 0..0)),
+        meta: Default::default(),
+        items: items
+            .into_iter()
+            .map(|item| Spanned::new(item, 0..0))
+            .collect(),
+    };
+
+    Spanned::new(scene, 0..0)
+}
+
+/// Source full of characters that mean something in novel, in any order.
+const SOURCE: &str = r"(== ?|- |> |[a-zA-Z :|@#{}*_~=/\[\]\\]|\r?\n|\t){0,80}";
+
+/// Any value that isn't rich text, other than a list.
+fn typed_value() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        (-100_000i32..100_000, 0u32..3).prop_map(|(number, decimals)| {
+            let number = f64::from(number) / 10f64.powi(decimals as i32);
+            Value::Number(Typed::new(number, number.to_string()))
+        }),
+        any::<bool>().prop_map(Value::Boolean),
+        (0u16..10_000, 1u8..=12, 1u8..=28).prop_map(|(year, month, day)| {
+            let date = Date { year, month, day };
+            Value::Date(Typed::new(date, date.to_string()))
+        }),
+        (0u8..24, 0u8..60, prop::option::of(0u8..60)).prop_map(|(hour, minute, second)| {
+            let time = Time {
+                hour,
+                minute,
+                second,
+            };
+            Value::Time(Typed::new(time, time.to_string()))
+        }),
+        (1u64..1_000_000).prop_map(|seconds| {
+            let duration = Duration { seconds };
+            Value::Duration(Typed::new(duration, duration.to_string()))
+        }),
+        (
+            "[a-zA-Z][a-zA-Z ]{0,10}[a-zA-Z]",
+            prop::option::of("[a-zA-Z][a-zA-Z ]{0,10}[a-zA-Z]")
+        )
+            .prop_map(|(referent, alias)| {
+                Value::Reference(Spanned::new(Reference { referent, alias }, 0..0))
+            }),
+    ]
+}
+
+/// A line of text full of characters that mean something in novel.
+const LINE: &str = r"[a-zA-Z@#}*_~=>\-\[\]\\][a-zA-Z0-9 .,:!?*_~=@#{}>\-\[\]\\]{0,40}";
+
+proptest! {
+    /// Scripts put together from the corpus' scenes, in any order, like a tool reordering them would.
+    #[test]
+    fn rearranged_scenes_round_trip(picks in prop::collection::vec((any::<Index>(), any::<Index>()), 0..16)) {
+        let scenes = picks
+            .iter()
+            .filter_map(|(file, scene)| {
+                let (_, _, file) = file.get(&CORPUS);
+                (!file.scenes.is_empty()).then(|| scene.get(&file.scenes).clone())
+            })
+            .collect();
+
+        assert_round_trips(&File { frontmatter: Some(Default::default()), scenes });
+    }
+
+    /// Anything at all, including what doesn't parse.
+    #[test]
+    fn syntax_trees_keep_any_source(source in SOURCE) {
+        let (tree, _) = parse_tree_recovering(&source);
+        prop_assert_eq!(tree.text(), source.clone());
+
+        // Whatever is in the tree can be lowered to a file.
+        parse_recovering(&source);
+    }
+
+    /// A title and subtitle, which may not be written as a title block, and other frontmatter.
+    #[test]
+    fn frontmatter_round_trips(
+        title in LINE,
+        subtitle in prop::option::of(LINE),
+        properties in prop::collection::vec(("[a-z][a-zA-Z0-9]{0,8}", LINE), 0..3),
+    ) {
+        let entry = |key: &str, value: &str| {
+            (
+                Spanned::new(key.to_owned(), 0..0),
+                Spanned::new(Value::RichText(RichText(vec![text(value)])), 0..0),
+            )
+        };
+
+        let mut frontmatter: Metadata = properties.iter().map(|(key, value)| entry(key, value)).collect();
+        frontmatter.extend([entry("Title", &title)]);
+        frontmatter.extend(subtitle.iter().map(|subtitle| entry("Subtitle", subtitle)));
+
+        assert_round_trips(&File { frontmatter: Some(frontmatter), scenes: vec![scene(vec![])] });
+    }
+
+    /// Typed values, alone and in lists, in the frontmatter and in scenes.
+    #[test]
+    fn typed_values_round_trip(
+        values in prop::collection::vec(prop::collection::vec(typed_value(), 1..4), 1..4),
+    ) {
+        let values: Vec<Value> = values
+            .into_iter()
+            .map(|mut items| match items.len() {
+                1 => items.remove(0),
+                _ => Value::List(items.into_iter().map(|item| Spanned::new(item, 0..0)).collect()),
+            })
+            .collect();
+        let meta: Metadata = values
+            .into_iter()
+            .enumerate()
+            .map(|(idx, value)| (Spanned::new(format!("Key{idx}"), 0..0), Spanned::new(value, 0..0)))
+            .collect();
+
+        let mut scene = scene(vec![]);
+        scene.node.meta = meta.clone();
+        assert_round_trips(&File { frontmatter: Some(meta), scenes: vec![scene] });
+    }
+
+    /// Text with stray formatting markers, brackets, braces and directive characters anywhere.
+    #[test]
+    fn text_round_trips(
+        action in prop::collection::vec(LINE, 1..4),
+        dialogue in prop::collection::vec(LINE, 1..4),
+        referent in "[a-zA-Z][a-zA-Z ]{0,10}[a-zA-Z]",
+        variable in "[a-zA-Z][a-zA-Z0-9_]{0,10}",
+    ) {
+        let mut parts = vec![text(&format!("{} ", action.join("\n")))];
+        parts.push(Spanned::new(
+            RichTextPart::Reference(Spanned::new(
+                Reference { referent: referent.clone(), alias: None },
+                0..0,
+            )),
+            0..0,
+        ));
+        parts.push(text(" and "));
+        parts.push(Spanned::new(RichTextPart::Variable(variable), 0..0));
+        parts.push(text("."));
+
+        let speaker = Spanned::new(Reference { referent, alias: Some("Someone".to_owned()) }, 0..0);
+        let block = dialogue.iter().map(|line| RichText(vec![text(line)])).collect();
+        let file = File {
+            frontmatter: Some(Default::default()),
+            scenes: vec![scene(vec![
+                SceneItem::ActionBlock(RichText(parts)),
+                SceneItem::DialogueBlock { speaker, block },
+            ])],
+        };
+
+        assert_round_trips(&file);
+    }
+}